@@ -74,6 +74,22 @@ impl Vector {
     let m = self * other;
     m.sum()
   }
+  pub fn cross(&self, other: &Vector) -> Vector {
+    Vector {
+      x: self.y*other.z - self.z*other.y,
+      y: self.z*other.x - self.x*other.z,
+      z: self.x*other.y - self.y*other.x
+    }
+  }
+  // um vetor unitário qualquer perpendicular a este
+  pub fn perpendicular(&self) -> Vector {
+    let helper = if self.x.abs() < 0.9 {
+      Vector::from(1.0, 0.0, 0.0)
+    } else {
+      Vector::from(0.0, 1.0, 0.0)
+    };
+    self.cross(&helper).normalized()
+  }
   pub fn dot_point(&self, point: &Point) -> f32 {
     self.x*point.x + self.y*point.y + self.z*point.z
   }
@@ -131,6 +147,17 @@ impl Color {
   pub fn as_array(self) -> [u8; 3] {
     [(self.r*255.0) as u8, (self.g*255.0) as u8, (self.b*255.0) as u8]
   }
+  // o PNG guarda a cor sem multiplicar pelo alfa
+  pub fn unpremultiply(&self, alpha: f32) -> Color {
+    if alpha > 0.0 {
+      self / alpha
+    } else {
+      self.clone()
+    }
+  }
+  pub fn to_rgba(&self, alpha: f32) -> [u8; 4] {
+    [(self.r*255.0) as u8, (self.g*255.0) as u8, (self.b*255.0) as u8, (alpha*255.0) as u8]
+  }
   pub fn from(r:f32, g: f32, b: f32) -> Color {
     Color {r, g, b}
   }
@@ -140,6 +167,32 @@ impl Color {
   pub fn black() -> Color {
     Color::from(0.0, 0.0, 0.0)
   }
+  pub fn white() -> Color {
+    Color::from(1.0, 1.0, 1.0)
+  }
+  pub fn clone(&self) -> Color {
+    Color {
+      r: self.r,
+      g: self.g,
+      b: self.b
+    }
+  }
+  pub fn is_black(&self) -> bool {
+    self.r <= 0.0 && self.g <= 0.0 && self.b <= 0.0
+  }
+  pub fn max_component(&self) -> f32 {
+    self.r.max(self.g).max(self.b)
+  }
+  pub fn luminance(&self) -> f32 {
+    0.2126*self.r + 0.7152*self.g + 0.0722*self.b
+  }
+  pub fn powf(&self, e: f32) -> Color {
+    Color {
+      r: self.r.powf(e),
+      g: self.g.powf(e),
+      b: self.b.powf(e)
+    }
+  }
 }
 
 // operadores unários
@@ -0,0 +1,681 @@
+use super::base::{Vector, Point, Color};
+use super::objets::{Camera, Object, Material, Light, LightKind, Ray, Background, refract, direction_around, sample_cone, cone_pdf};
+use super::sampler;
+use rand::Rng;
+use std::f32::consts::PI;
+
+// Traçado de caminhos bidirecional (Veach, 1997), no formato do pbrt:
+// para cada amostra, um caminho parte da câmera e outro de uma luz, e
+// todos os pares de vértices são conectados. Cada conexão é uma estratégia
+// diferente para o mesmo caminho de luz, e elas são combinadas por
+// amostragem múltipla por importância (heurística do balanço).
+//
+// Diferente do renderizador de sempre, a iluminação aqui é física: as
+// luzes sempre caem com o quadrado da distância, a luz ambiente não é
+// usada (o fundo ilumina a cena) e os volumes e o meio global são ignorados.
+
+// deslocamento das origens dos raios, para não colidirem com a própria superfície
+const EPSILON: f32 = 1e-3;
+
+// O material reduzido a três lobos, escolhidos com probabilidade
+// proporcional a kd, ks e kt (o que faltar para 1 é absorvido): difuso
+// lambertiano, espelho perfeito e refração perfeita. Só o lobo difuso
+// pode ser conectado a outro caminho
+struct Bsdf {
+  rgb: Color,
+  difuse: f32,
+  mirror: f32,
+  transmit: f32,
+  ior: f32
+}
+impl Bsdf {
+  fn from(material: &Material, uv: (f32, f32), point: &Point) -> Bsdf {
+    let kd = material.kd_at(uv, point).max(0.0);
+    let ks = material.ks_at(uv, point).max(0.0);
+    let kt = material.kt_at(uv, point).max(0.0);
+    let total = (kd + ks + kt).max(1.0);
+    Bsdf {
+      rgb: material.color_at(uv, point),
+      difuse: kd / total,
+      mirror: ks / total,
+      transmit: kt / total,
+      ior: material.ior(None)
+    }
+  }
+  fn connectible(&self) -> bool {
+    self.difuse > 0.0
+  }
+  // parte difusa da BSDF, entre a direção `wo` e a direção `wi`
+  fn eval(&self, normal: &Vector, wo: &Vector, wi: &Vector) -> Color {
+    if normal.dot(wo) * normal.dot(wi) <= 0.0 {
+      return Color::black();
+    }
+    &self.rgb * (self.difuse / PI)
+  }
+  // densidade (em ângulo sólido) de amostrar `wi` a partir de `wo`
+  fn pdf(&self, normal: &Vector, wo: &Vector, wi: &Vector) -> f32 {
+    if normal.dot(wo) * normal.dot(wi) <= 0.0 {
+      return 0.0;
+    }
+    self.difuse * normal.dot(wi).abs() / PI
+  }
+  // direção, peso (f·cos/pdf), pdf e se o lobo escolhido é especular
+  fn sample<R: Rng>(&self, normal: &Vector, wo: &Vector, rng: &mut R) -> Option<(Vector, Color, f32, bool)> {
+    let u: f32 = rng.gen();
+    let outside = normal.dot(wo) > 0.0;
+    let side = if outside { normal.clone() } else { -normal };
+    let mirror = || 2.0 * side.dot(wo) * &side - wo;
+    if u < self.difuse {
+      let wi = cosine_hemisphere(&side, rng);
+      let pdf = self.difuse * side.dot(&wi) / PI;
+      return Some((wi, self.rgb.clone(), pdf, false));
+    }
+    if u < self.difuse + self.mirror {
+      return Some((mirror(), Color::white(), 0.0, true));
+    }
+    if u < self.difuse + self.mirror + self.transmit {
+      let eta = if outside { 1.0 / self.ior } else { self.ior };
+      let wi = refract(&-wo, &side, eta).unwrap_or_else(mirror);
+      // como nos raios de sombra, a cor tinge a luz ao entrar no objeto
+      let weight = if outside { self.rgb.clone() } else { Color::white() };
+      return Some((wi, weight, 0.0, true));
+    }
+    None
+  }
+}
+fn cosine_hemisphere<R: Rng>(normal: &Vector, rng: &mut R) -> Vector {
+  let (u1, u2): (f32, f32) = (rng.gen(), rng.gen());
+  direction_around(normal, (1.0 - u1).max(0.0).sqrt(), 2.0 * PI * u2)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+  Camera,
+  Light,
+  Surface
+}
+struct Vertex {
+  kind: Kind,
+  point: Point,
+  normal: Vector, // nula na câmera e nas luzes
+  wo: Vector, // direção para o vértice anterior
+  beta: Color, // contribuição do caminho até aqui, dividida pelas pdfs
+  pdf_fwd: f32, // densidade (por área) de gerar este vértice a partir do anterior
+  pdf_rev: f32, // e a partir do seguinte, no sentido contrário
+  delta: bool, // saiu daqui por um lobo especular
+  light: usize, // índice da luz, nos vértices de luz
+  bsdf: Option<Bsdf>
+}
+impl Vertex {
+  fn at(kind: Kind, point: Point, beta: Color, pdf_fwd: f32) -> Vertex {
+    Vertex {
+      kind, point, beta, pdf_fwd,
+      normal: Vector::from(0.0, 0.0, 0.0),
+      wo: Vector::from(0.0, 0.0, 0.0),
+      pdf_rev: 0.0,
+      delta: false,
+      light: 0,
+      bsdf: None
+    }
+  }
+  fn connectible(&self) -> bool {
+    match &self.bsdf {
+      Some(bsdf) => bsdf.connectible(),
+      None => true
+    }
+  }
+  // f da superfície para luz indo deste vértice até `point`
+  fn f(&self, point: &Point) -> Color {
+    match &self.bsdf {
+      Some(bsdf) => bsdf.eval(&self.normal, &self.wo, &(point - &self.point).normalized()),
+      None => Color::black()
+    }
+  }
+  // |cos| entre a normal e a direção até `point` (1 fora das superfícies)
+  fn cos_to(&self, point: &Point) -> f32 {
+    match self.kind {
+      Kind::Surface => self.normal.dot(&(point - &self.point).normalized()).abs(),
+      _ => 1.0
+    }
+  }
+}
+// converte uma densidade em ângulo sólido, medida em `from`, para
+// densidade por área em `to`
+fn to_area(pdf: f32, from: &Point, to: &Vertex) -> f32 {
+  let w = &to.point - from;
+  let distance2 = w.dot(&w);
+  if distance2 <= 0.0 {
+    return 0.0;
+  }
+  let pdf = pdf / distance2;
+  match to.kind {
+    Kind::Surface => pdf * to.normal.dot(&w.normalized()).abs(),
+    _ => pdf
+  }
+}
+
+// (pdf_fwd, pdf_rev, delta) de um vértice, como visto por uma estratégia
+type Densities = (f32, f32, bool);
+
+// luz emitida por uma luz pontual ou spot na direção `direction`,
+// em intensidade. Luzes de cor C têm intensidade πC, para ficarem na
+// mesma escala do modelo de Phong (que não divide o difuso por π)
+fn emitted(light: &Light, direction: &Vector) -> Color {
+  &light.difuse * light.cone_at(&(&light.xyz + direction)) * (PI * light.intensity)
+}
+// densidade (em ângulo sólido) de uma luz emitir na direção `direction`
+fn emission_pdf(light: &Light, direction: &Vector) -> f32 {
+  match &light.kind {
+    LightKind::Spot { direction: axis, outer, .. } => {
+      if axis.normalized().dot(&direction.normalized()) < outer.cos() {
+        return 0.0;
+      }
+      cone_pdf(outer.cos())
+    },
+    _ => cone_pdf(-1.0)
+  }
+}
+fn sample_emission<R: Rng>(light: &Light, rng: &mut R) -> Vector {
+  let (u1, u2): (f32, f32) = (rng.gen(), rng.gen());
+  let (axis, cos_max) = match &light.kind {
+    LightKind::Spot { direction, outer, .. } => (direction.normalized(), outer.cos()),
+    _ => (Vector::from(0.0, 1.0, 0.0), -1.0)
+  };
+  sample_cone(&axis, cos_max, u1, u2)
+}
+
+impl Camera {
+  pub fn take_bidirectional_picture(&self, objects: &Vec<Box<dyn Object>>, lights: &[Light], samples: u32, max_depth: u32) -> Vec<Vec<[u8; 4]>> {
+    let w = self.width as usize;
+    let h = self.height as usize;
+    let samples = samples.max(1);
+    let max_depth = max_depth.max(1) as usize;
+    let mut rng = sampler::rng();
+    let black = || (0..w).map(|_| (0..h).map(|_| Color::black()).collect::<Vec<Color>>()).collect::<Vec<_>>();
+    let mut film = black();
+    let mut splats = black();
+    let mut coverage = vec![vec![0.0; h]; w];
+    let mut camera_path = Vec::with_capacity(max_depth + 1);
+    let mut light_path = Vec::with_capacity(max_depth + 1);
+
+    for x in 0..w {
+      for y in 0..h {
+        for i in 0..samples {
+          sampler::start(x as u32, y as u32, i, samples);
+          camera_path.clear();
+          light_path.clear();
+          let (rx, ry) = sampler::next_2d();
+          let escaped = self.camera_subpath(x as f32 + rx, y as f32 + ry, objects, max_depth, &mut camera_path, &mut rng);
+          self.light_subpath(objects, lights, max_depth, &mut light_path, &mut rng);
+
+          let hit = camera_path.len() > 1;
+          if hit || !self.transparent {
+            coverage[x][y] += 1.0;
+          }
+          if let Some((ray, beta, pdf, delta)) = escaped {
+            if hit || !self.transparent {
+              film[x][y] += self.escaped_radiance(&ray, &beta, pdf, delta || !hit);
+            }
+          }
+          // a iluminação direta (s = 1) sorteia a própria luz, então é
+          // calculada mesmo quando o caminho da luz ficou vazio. Sem luzes
+          // (cenas iluminadas só pelo fundo) não há o que conectar
+          let light_vertices = if lights.is_empty() { 0 } else { light_path.len().max(1) };
+          for t in 1..=camera_path.len() {
+            for s in 0..=light_vertices {
+              if s == 0 || (s == 1 && t == 1) || s + t - 2 > max_depth {
+                continue;
+              }
+              let (color, raster) = self.connect(&light_path, &camera_path, s, t, objects, lights, &mut rng);
+              match raster {
+                Some((px, py)) => splats[px][py] += color,
+                None => film[x][y] += color
+              }
+            }
+          }
+          if self.environment.is_some() {
+            for vertex in camera_path.iter().skip(1) {
+              film[x][y] += self.environment_light(vertex, objects, &mut rng);
+            }
+          }
+        }
+      }
+    }
+
+    let mut colors = vec![vec![[0u8; 4]; h]; w];
+    for (x, column) in colors.iter_mut().enumerate() {
+      for (y, pixel) in column.iter_mut().enumerate() {
+        let alpha = coverage[x][y] / samples as f32;
+        let color = ((&film[x][y] + &splats[x][y]) / samples as f32).unpremultiply(alpha);
+        *pixel = color.clip().to_rgba(alpha);
+      }
+    }
+    if let Some(outline) = &self.outline {
+      self.draw_outlines(outline, &mut colors, objects);
+    }
+    colors
+  }
+
+  // --- câmera
+  fn film_area(&self) -> f32 {
+    let bounds = self.image_plane_bounds();
+    (&bounds.tr - &bounds.tl).norm() * (&bounds.bl - &bounds.tl).norm()
+  }
+  // densidade (em ângulo sólido) de um raio da câmera sair na direção
+  // `direction`, com o filme todo amostrado uniformemente
+  fn camera_pdf(&self, direction: &Vector) -> f32 {
+    let cos_theta = self.target.dot(&direction.normalized());
+    if cos_theta <= 0.0 {
+      return 0.0;
+    }
+    let d = self.image_plane_distance;
+    d * d / (self.film_area() * cos_theta.powi(3))
+  }
+  // pixel atingido por um raio da câmera na direção `direction`
+  fn raster(&self, direction: &Vector) -> Option<(usize, usize)> {
+    let cos_theta = self.target.dot(&direction.normalized());
+    if cos_theta <= 0.0 {
+      return None;
+    }
+    let bounds = self.image_plane_bounds();
+    let point = &self.xyz + &direction.normalized() * (self.image_plane_distance / cos_theta);
+    let across = &bounds.tr - &bounds.tl;
+    let down = &bounds.bl - &bounds.tl;
+    let offset = &point - &bounds.tl;
+    let xt = offset.dot(&across) / across.dot(&across);
+    let yt = offset.dot(&down) / down.dot(&down);
+    if !(0.0..1.0).contains(&xt) || !(0.0..1.0).contains(&yt) {
+      return None;
+    }
+    Some(((xt * self.width as f32) as usize, (yt * self.height as f32) as usize))
+  }
+
+  // --- caminhos
+  fn closest_surface(&self, ray: &Ray, objects: &Vec<Box<dyn Object>>) -> Option<(usize, f32)> {
+    let mut closest = None;
+    for (i, item) in objects.iter().enumerate() {
+      if item.volume().is_some() {
+        continue;
+      }
+      if let Some(t) = item.ray_intersection_distance(ray) {
+        match closest {
+          Some((_, best)) if best <= t => (),
+          _ if t > 0.0 => closest = Some((i, t)),
+          _ => ()
+        }
+      }
+    }
+    closest
+  }
+  fn visible(&self, a: &Point, b: &Point, objects: &Vec<Box<dyn Object>>) -> bool {
+    let w = b - a;
+    let distance = w.norm();
+    let direction = w.normalized();
+    let ray = Ray {
+      origin: a + &direction * EPSILON,
+      direction,
+      wavelength: None
+    };
+    match self.closest_surface(&ray, objects) {
+      Some((_, t)) => t >= distance - 2.0 * EPSILON,
+      None => true
+    }
+  }
+  fn escapes(&self, point: &Point, direction: &Vector, objects: &Vec<Box<dyn Object>>) -> bool {
+    let ray = Ray {
+      origin: point + direction * EPSILON,
+      direction: direction.clone(),
+      wavelength: None
+    };
+    self.closest_surface(&ray, objects).is_none()
+  }
+  // Estende `path` a partir de `ray`, cuja direção foi amostrada com
+  // densidade `pdf` a partir do último vértice. Se o caminho sai da cena,
+  // devolve o raio, a contribuição, a pdf e se a última escolha foi especular
+  #[allow(clippy::too_many_arguments)]
+  fn random_walk<R: Rng>(&self, mut ray: Ray, mut beta: Color, mut pdf: f32, max_depth: usize, objects: &Vec<Box<dyn Object>>, path: &mut Vec<Vertex>, rng: &mut R) -> Option<(Ray, Color, f32, bool)> {
+    let mut delta = false;
+    for bounce in 0..max_depth {
+      let (index, t) = match self.closest_surface(&ray, objects) {
+        Some(hit) => hit,
+        None => return Some((ray, beta, pdf, delta))
+      };
+      let item = &objects[index];
+      let material = item.material();
+      let point = ray.point_at(t);
+      let uv = item.uv_at_point(&point);
+      let normal = item.normal_at_point(&point);
+      // dentro de um objeto transparente, a luz é absorvida pelo caminho
+      if normal.dot(&ray.direction) > 0.0 && material.kt > 0.0 {
+        beta *= material.transmittance(t);
+      }
+      let mut vertex = Vertex::at(Kind::Surface, point, beta.clone(), 0.0);
+      vertex.normal = normal;
+      vertex.wo = (-&ray.direction).normalized();
+      vertex.bsdf = Some(Bsdf::from(material, uv, &vertex.point));
+      let previous = path.len() - 1;
+      vertex.pdf_fwd = to_area(pdf, &path[previous].point, &vertex);
+      path.push(vertex);
+      if bounce + 1 == max_depth {
+        return None;
+      }
+
+      let current = path.len() - 1;
+      let vertex = &path[current];
+      let bsdf = vertex.bsdf.as_ref()?;
+      let (wi, weight, sampled_pdf, sampled_delta) = bsdf.sample(&vertex.normal, &vertex.wo, rng)?;
+      let reverse = if sampled_delta { 0.0 } else { bsdf.pdf(&vertex.normal, &wi, &vertex.wo) };
+      let origin = &vertex.point + &wi * EPSILON;
+      let reverse = to_area(reverse, &vertex.point, &path[previous]);
+      path[previous].pdf_rev = reverse;
+      path[current].delta = sampled_delta;
+      beta *= weight;
+      if beta.is_black() {
+        return None;
+      }
+      pdf = sampled_pdf;
+      delta = sampled_delta;
+      ray = Ray { origin, direction: wi, wavelength: None };
+    }
+    None
+  }
+  fn camera_subpath<R: Rng>(&self, x: f32, y: f32, objects: &Vec<Box<dyn Object>>, max_depth: usize, path: &mut Vec<Vertex>, rng: &mut R) -> Option<(Ray, Color, f32, bool)> {
+    let bounds = self.image_plane_bounds();
+    let film_point = self.ray_for_pixel(x, y, &bounds).point_at(0.0);
+    let direction = (&film_point - &self.xyz).normalized();
+    let pdf = self.camera_pdf(&direction);
+    path.push(Vertex::at(Kind::Camera, self.xyz.clone(), Color::white(), 0.0));
+    let ray = Ray { origin: self.xyz.clone(), direction, wavelength: None };
+    self.random_walk(ray, Color::white(), pdf, max_depth, objects, path, rng)
+  }
+  // Luzes escolhidas com probabilidade uniforme; as direcionais não
+  // emitem caminhos (não há como amostrar uma origem para elas numa cena
+  // com planos infinitos) e entram só pelas conexões diretas
+  fn light_subpath<R: Rng>(&self, objects: &Vec<Box<dyn Object>>, lights: &[Light], max_depth: usize, path: &mut Vec<Vertex>, rng: &mut R) {
+    if lights.is_empty() {
+      return;
+    }
+    let index = rng.gen_range(0, lights.len());
+    let light = &lights[index];
+    if let LightKind::Directional { .. } = light.kind {
+      return;
+    }
+    let choice = 1.0 / lights.len() as f32;
+    let direction = sample_emission(light, rng);
+    let pdf = emission_pdf(light, &direction);
+    let le = emitted(light, &direction);
+    let mut vertex = Vertex::at(Kind::Light, light.xyz.clone(), le.clone(), choice);
+    vertex.light = index;
+    path.push(vertex);
+    let ray = Ray { origin: light.xyz.clone(), direction, wavelength: None };
+    self.random_walk(ray, le / (choice * pdf), pdf, max_depth - 1, objects, path, rng);
+  }
+
+  // --- conexões
+  // densidade (por área) de `vertex` gerar `next`, tendo vindo de `previous`
+  fn pdf_between(&self, previous: Option<&Vertex>, vertex: &Vertex, next: &Vertex, lights: &[Light]) -> f32 {
+    let direction = (&next.point - &vertex.point).normalized();
+    let pdf = match vertex.kind {
+      Kind::Camera => self.camera_pdf(&direction),
+      Kind::Light => emission_pdf(&lights[vertex.light], &direction),
+      Kind::Surface => match (previous, &vertex.bsdf) {
+        (Some(previous), Some(bsdf)) => {
+          bsdf.pdf(&vertex.normal, &(&previous.point - &vertex.point).normalized(), &direction)
+        },
+        _ => 0.0
+      }
+    };
+    to_area(pdf, &vertex.point, next)
+  }
+  // Peso da estratégia (s, t) pela heurística do balanço: compara a
+  // densidade desta estratégia com a de todas as outras que gerariam o
+  // mesmo caminho, trocando um vértice de lado por vez. `sampled` é o
+  // vértice criado na conexão, que substitui o primeiro de um dos lados
+  fn mis_weight(&self, light_path: &[Vertex], camera_path: &[Vertex], sampled: Option<&Vertex>, s: usize, t: usize, lights: &[Light]) -> f32 {
+    if s + t == 2 {
+      return 1.0;
+    }
+    let light_vertex = |i: usize| match sampled {
+      Some(vertex) if s == 1 && i == 0 => vertex,
+      _ => &light_path[i]
+    };
+    let camera_vertex = |i: usize| match sampled {
+      Some(vertex) if t == 1 && i == 0 => vertex,
+      _ => &camera_path[i]
+    };
+    let mut light: Vec<Densities> = (0..s).map(|i| {
+      let v = light_vertex(i);
+      (v.pdf_fwd, v.pdf_rev, v.delta)
+    }).collect();
+    let mut camera: Vec<Densities> = (0..t).map(|i| {
+      let v = camera_vertex(i);
+      (v.pdf_fwd, v.pdf_rev, v.delta)
+    }).collect();
+
+    let pt = camera_vertex(t - 1);
+    let pt_minus = if t > 1 { Some(camera_vertex(t - 2)) } else { None };
+    let qs = light_vertex(s - 1);
+    let qs_minus = if s > 1 { Some(light_vertex(s - 2)) } else { None };
+    // os dois vértices da conexão não são especulares nesta estratégia
+    camera[t - 1].2 = false;
+    light[s - 1].2 = false;
+    camera[t - 1].1 = self.pdf_between(qs_minus, qs, pt, lights);
+    if let Some(pt_minus) = pt_minus {
+      camera[t - 2].1 = self.pdf_between(Some(qs), pt, pt_minus, lights);
+    }
+    light[s - 1].1 = self.pdf_between(pt_minus, pt, qs, lights);
+    if let Some(qs_minus) = qs_minus {
+      light[s - 2].1 = self.pdf_between(Some(pt), qs, qs_minus, lights);
+    }
+
+    // densidades nulas (vértices especulares) não entram na razão
+    let remap = |pdf: f32| if pdf != 0.0 { pdf } else { 1.0 };
+    let mut sum = 0.0;
+    let mut ratio = 1.0;
+    for i in (1..t).rev() {
+      ratio *= remap(camera[i].1) / remap(camera[i].0);
+      if !camera[i].2 && !camera[i - 1].2 {
+        sum += ratio;
+      }
+    }
+    ratio = 1.0;
+    for i in (0..s).rev() {
+      ratio *= remap(light[i].1) / remap(light[i].0);
+      // todas as luzes são pontuais: nenhum caminho da câmera as atinge
+      let delta_light = if i > 0 { light[i - 1].2 } else { true };
+      if !light[i].2 && !delta_light {
+        sum += ratio;
+      }
+    }
+    1.0 / (1.0 + sum)
+  }
+  // Contribuição da estratégia com `s` vértices do caminho da luz e `t`
+  // da câmera. Com t = 1 o vértice da luz é ligado direto à câmera e a
+  // cor vai para outro pixel, devolvido junto
+  #[allow(clippy::too_many_arguments)]
+  fn connect<R: Rng>(&self, light_path: &[Vertex], camera_path: &[Vertex], s: usize, t: usize, objects: &Vec<Box<dyn Object>>, lights: &[Light], rng: &mut R) -> (Color, Option<(usize, usize)>) {
+    let none = (Color::black(), None);
+    if t == 1 {
+      let qs = &light_path[s - 1];
+      if !qs.connectible() || qs.kind != Kind::Surface {
+        return none;
+      }
+      let to_point = &qs.point - &self.xyz;
+      let raster = match self.raster(&to_point) {
+        Some(raster) => raster,
+        None => return none
+      };
+      if !self.visible(&qs.point, &self.xyz, objects) {
+        return none;
+      }
+      // importância da câmera estenopeica, já convertida para a área em qs
+      let cos_theta = self.target.dot(&to_point.normalized());
+      let d = self.image_plane_distance;
+      let importance = d * d / (self.film_area() * cos_theta.powi(3) * to_point.dot(&to_point));
+      let color = &qs.beta * qs.f(&self.xyz) * (qs.cos_to(&self.xyz) * importance);
+      if color.is_black() {
+        return none;
+      }
+      let camera = Vertex::at(Kind::Camera, self.xyz.clone(), Color::white(), 0.0);
+      let weight = self.mis_weight(light_path, camera_path, Some(&camera), s, t, lights);
+      // cada caminho da luz acompanha uma amostra de pixel, então as
+      // contribuições espalhadas também são divididas pelo número de amostras
+      return (color * weight, Some(raster));
+    }
+    let pt = &camera_path[t - 1];
+    if !pt.connectible() {
+      return none;
+    }
+    if s == 1 {
+      // iluminação direta: uma luz escolhida ao acaso
+      let index = rng.gen_range(0, lights.len());
+      let light = &lights[index];
+      let choice = 1.0 / lights.len() as f32;
+      if let LightKind::Directional { direction } = &light.kind {
+        let wi = (-direction).normalized();
+        if !self.escapes(&pt.point, &wi, objects) {
+          return none;
+        }
+        // só esta estratégia gera caminhos vindos de luzes direcionais
+        let irradiance = &light.difuse * (PI * light.intensity / choice);
+        let target = &pt.point + &wi;
+        return (&pt.beta * pt.f(&target) * pt.cos_to(&target) * irradiance, None);
+      }
+      let to_point = &pt.point - &light.xyz;
+      let le = emitted(light, &to_point.normalized());
+      if le.is_black() || !self.visible(&pt.point, &light.xyz, objects) {
+        return none;
+      }
+      let mut sampled = Vertex::at(Kind::Light, light.xyz.clone(), le.clone(), choice);
+      sampled.light = index;
+      let color = &pt.beta * pt.f(&light.xyz) * (pt.cos_to(&light.xyz) / (choice * to_point.dot(&to_point))) * le;
+      let weight = self.mis_weight(light_path, camera_path, Some(&sampled), s, t, lights);
+      return (color * weight, None);
+    }
+    let qs = &light_path[s - 1];
+    if !qs.connectible() {
+      return none;
+    }
+    let w = &pt.point - &qs.point;
+    let g = qs.cos_to(&pt.point) * pt.cos_to(&qs.point) / w.dot(&w);
+    let color = &qs.beta * qs.f(&pt.point) * pt.f(&qs.point) * &pt.beta * g;
+    if color.is_black() || !self.visible(&pt.point, &qs.point, objects) {
+      return none;
+    }
+    let weight = self.mis_weight(light_path, camera_path, None, s, t, lights);
+    (color * weight, None)
+  }
+
+  // --- fundo
+  // Radiância do fundo para um caminho da câmera que saiu da cena.
+  // Com um mapa de ambiente, a mesma luz também é amostrada em cada
+  // vértice (`environment_light`), e as duas estratégias são ponderadas
+  // pela heurística da potência
+  fn escaped_radiance(&self, ray: &Ray, beta: &Color, pdf: f32, delta: bool) -> Color {
+    let radiance = beta * self.bg_color_for_ray(ray);
+    let environment = match (&self.background, &self.environment) {
+      (Background::Environment { .. }, Some(environment)) if environment.samples > 0 => environment,
+      _ => return radiance
+    };
+    if delta {
+      return radiance;
+    }
+    let light_pdf = environment.pdf(&ray.direction);
+    radiance * (pdf * pdf / (pdf * pdf + light_pdf * light_pdf))
+  }
+  fn environment_light<R: Rng>(&self, vertex: &Vertex, objects: &Vec<Box<dyn Object>>, rng: &mut R) -> Color {
+    let environment = match (&self.background, &self.environment) {
+      (Background::Environment { .. }, Some(environment)) if environment.samples > 0 => environment,
+      _ => return Color::black()
+    };
+    let bsdf = match &vertex.bsdf {
+      Some(bsdf) if bsdf.connectible() => bsdf,
+      _ => return Color::black()
+    };
+    let mut color = Color::black();
+    for _ in 0..environment.samples {
+      let (wi, radiance, pdf) = environment.sample(rng.gen(), rng.gen());
+      if pdf <= 0.0 || !self.escapes(&vertex.point, &wi, objects) {
+        continue;
+      }
+      let f = bsdf.eval(&vertex.normal, &vertex.wo, &wi);
+      let bsdf_pdf = bsdf.pdf(&vertex.normal, &vertex.wo, &wi);
+      let weight = pdf * pdf / (pdf * pdf + bsdf_pdf * bsdf_pdf);
+      color += &vertex.beta * f * radiance * (vertex.normal.dot(&wi).abs() * weight / pdf);
+    }
+    color / environment.samples as f32
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::{Integrator, ShadingModel};
+  use crate::environment::Environment;
+  use crate::objets::Plane;
+
+  fn floor() -> Box<dyn Object> {
+    let material = Material {
+      rgb: Color::from(0.8, 0.8, 0.8),
+      kd: 0.8, ks: 0.0, kt: 0.0, kr: 1.0,
+      dispersion: None,
+      alpha: 1.0,
+      model: ShadingModel::default(),
+      metallic: 0.0,
+      roughness: 0.0,
+      bands: 1,
+      glossy_samples: 1,
+      absorption: Color::white(),
+      density: 0.0,
+      medium: None,
+      texture: None,
+      kd_texture: None,
+      ks_texture: None,
+      kt_texture: None,
+      normal_map: None,
+      bump: None,
+      bump_strength: 1.0
+    };
+    Box::new(Plane::from(material, Vector::from(0.0, 1.0, 0.0), &Point::from(0.0, -1.0, 0.0)))
+  }
+
+  // fundo de uma cor só, salvo como imagem comum
+  fn environment() -> Environment {
+    let path = std::env::temp_dir().join("bidirectional_environment.png");
+    image::save_buffer(&path, &[128u8; 4 * 2 * 3], 4, 2, image::RGB(8)).unwrap();
+    Environment::open(path.to_str().unwrap(), 0.0, 1.0, 4).unwrap()
+  }
+
+  #[test]
+  fn scene_lit_only_by_the_environment_renders() {
+    let mut camera = Camera {
+      xyz: Point::from(0.0, 0.0, -1.0),
+      target: Vector::from(0.0, 0.0, 0.0),
+      ambient_light: Color::black(),
+      orientation: Vector::from(0.0, 1.0, 0.0),
+      fov: 0.0,
+      image_plane_distance: 0.0,
+      width: 4,
+      height: 3,
+      background: Background::Environment { file: String::new(), rotation: 0.0, intensity: 1.0, samples: 4 },
+      environment: Some(environment()),
+      sky: None,
+      transparent: false,
+      spectrum: None,
+      medium: None,
+      outline: None,
+      ambient_occlusion: None,
+      integrator: Integrator::Bidirectional { samples: 2, max_depth: 3 },
+      caustics: None,
+      light_sampler: None,
+      max_depth: 3,
+      min_weight: 0.0
+    };
+    camera.set_target(Point::from(0.0, -1.0, 5.0));
+    camera.set_image_plane_distance(1.0);
+    let pixels = camera.take_bidirectional_picture(&vec![floor()], &[], 2, 3);
+    assert_eq!(pixels.len(), 4);
+    // o chão só recebe a luz do fundo
+    assert!(pixels.iter().flatten().any(|pixel| pixel[0] > 0));
+  }
+}
@@ -0,0 +1,48 @@
+use super::base::{Vector, Color};
+use std::f32::consts::PI;
+
+// reflectância na incidência normal: 4% para dielétricos,
+// a própria cor da superfície para metais
+pub fn base_reflectance(base: &Color, metallic: f32) -> Color {
+  (1.0 - metallic) * Color::from(0.04, 0.04, 0.04) + metallic * base
+}
+// aproximação de Schlick
+pub fn fresnel(f0: &Color, cos_theta: f32) -> Color {
+  let k = (1.0 - cos_theta.max(0.0)).powi(5);
+  f0 + (Color::white() - f0) * k
+}
+// distribuição de microfacetas GGX (Trowbridge-Reitz)
+pub fn ggx_distribution(n_dot_h: f32, roughness: f32) -> f32 {
+  let a = (roughness * roughness).max(1e-3);
+  let a2 = a * a;
+  let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+  a2 / (PI * d * d)
+}
+// sombreamento-mascaramento de Smith (aproximação de Schlick-GGX)
+pub fn smith_geometry(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+  let k = (roughness * roughness).max(1e-3) / 2.0;
+  let g1 = |x: f32| x / (x * (1.0 - k) + k);
+  g1(n_dot_v) * g1(n_dot_l)
+}
+// Cook-Torrance. Retorna as parcelas difusa e especular já multiplicadas
+// por N·L e por π, para ficarem na mesma escala do modelo de Phong
+// (uma superfície lambertiana branca devolve kd * luz * N·L)
+pub fn cook_torrance(base: &Color, metallic: f32, roughness: f32, normal: &Vector, view: &Vector, light: &Vector) -> (Color, Color) {
+  let n_dot_l = normal.dot(light);
+  let n_dot_v = normal.dot(view).max(1e-4);
+  if n_dot_l <= 0.0 {
+    return (Color::black(), Color::black());
+  }
+  let half = (view + light).normalized();
+  let n_dot_h = normal.dot(&half).max(0.0);
+  let v_dot_h = view.dot(&half).max(0.0);
+
+  let f0 = base_reflectance(base, metallic);
+  let f = fresnel(&f0, v_dot_h);
+  let d = ggx_distribution(n_dot_h, roughness);
+  let g = smith_geometry(n_dot_v, n_dot_l, roughness);
+
+  let specular = &f * (PI * d * g / (4.0 * n_dot_v));
+  let difuse = (Color::white() - f) * (1.0 - metallic) * base * n_dot_l;
+  (difuse, specular)
+}
@@ -1,5 +1,7 @@
 use serde::{Deserialize};
-use serde_json::Result;
+use serde::de::Error;
+use serde_json::{Result, Value, Map};
+use std::fs;
 
 #[derive(Deserialize)]
 pub struct Camera {
@@ -19,34 +21,267 @@ pub struct Material {
   pub ks: f32,
   pub kt: f32,
   pub kr: f32,
-  pub alpha: f32
+  pub alpha: f32,
+  pub dispersion: Option<Dispersion>,
+  #[serde(default)]
+  pub model: ShadingModel,
+  #[serde(default)]
+  pub metallic: f32,
+  #[serde(default)]
+  pub roughness: f32,
+  #[serde(default = "default_glossy_samples")]
+  pub glossy_samples: u32,
+  #[serde(default = "default_bands")]
+  pub bands: u32,
+  // absorção (Beer–Lambert) para objetos transmissivos
+  #[serde(default = "default_absorption")]
+  pub absorption: [f32; 3],
+  #[serde(default)]
+  pub density: f32,
+  pub medium: Option<Medium>,
+  pub texture: Option<Texture>,
+  pub kd_texture: Option<Texture>,
+  pub ks_texture: Option<Texture>,
+  pub kt_texture: Option<Texture>,
+  pub normal_map: Option<Texture>,
+  pub bump: Option<Texture>,
+  #[serde(default = "default_one")]
+  pub bump_strength: f32
+}
+// coeficientes com comprimentos de onda em micrômetros
+#[derive(Deserialize, Clone, Copy)]
+pub enum Dispersion {
+  Cauchy { b: f32 },
+  Sellmeier { b: [f32; 3], c: [f32; 3] }
+}
+#[derive(Deserialize, Clone, Copy, Default)]
+pub enum ShadingModel {
+  #[default]
+  Phong,
+  Ggx,
+  Toon
+}
+#[derive(Deserialize, Clone, Copy, Default)]
+pub enum WrapMode {
+  #[default]
+  Repeat,
+  Clamp,
+  Mirror
+}
+#[derive(Deserialize, Default)]
+pub enum TextureType {
+  #[default]
+  Image,
+  Checker,
+  Noise,
+  Turbulence,
+  Marble,
+  Wood
+}
+#[derive(Deserialize)]
+pub struct Texture {
+  #[serde(default)]
+  pub kind: TextureType,
+  // image
+  #[serde(default)]
+  pub file: String,
+  #[serde(default)]
+  pub wrap: WrapMode,
+  #[serde(default = "default_scale")]
+  pub scale: [f32; 2],
+  // procedurais
+  #[serde(default = "default_texture_colors")]
+  pub colors: [[f32; 3]; 2],
+  #[serde(default = "default_one")]
+  pub frequency: f32,
+  #[serde(default = "default_octaves")]
+  pub octaves: u32,
+  #[serde(default = "default_strength")]
+  pub strength: f32,
+  #[serde(default)]
+  pub seed: u32
+}
+fn default_scale() -> [f32; 2] {
+  [1.0, 1.0]
+}
+fn default_texture_colors() -> [[f32; 3]; 2] {
+  [[0.0, 0.0, 0.0], [1.0, 1.0, 1.0]]
+}
+fn default_one() -> f32 {
+  1.0
+}
+fn default_octaves() -> u32 {
+  4
+}
+fn default_strength() -> f32 {
+  5.0
+}
+fn default_glossy_samples() -> u32 {
+  8
+}
+fn default_bands() -> u32 {
+  3
+}
+fn default_white() -> [f32; 3] {
+  [1.0, 1.0, 1.0]
+}
+fn default_absorption() -> [f32; 3] {
+  [1.0, 1.0, 1.0]
 }
 #[derive(Deserialize)]
 pub enum ObjectType {
   Plane,
-  Sphere
+  Sphere,
+  Volume
 }
 #[derive(Deserialize)]
 pub struct Object {
   pub kind: ObjectType,
   pub material: Material,
   // sphere
+  #[serde(default)]
   pub r: f32,
+  #[serde(default)]
   pub center: [f32; 3],
   // plane
+  #[serde(default)]
   pub normal: [f32; 3],
-  pub point: [f32; 3]
+  #[serde(default)]
+  pub point: [f32; 3],
+  // volume
+  #[serde(default)]
+  pub min: [f32; 3],
+  #[serde(default)]
+  pub max: [f32; 3],
+  pub density: Option<Density>,
+  #[serde(default = "default_one")]
+  pub density_scale: f32,
+  #[serde(default)]
+  pub g: f32
+}
+#[derive(Deserialize)]
+pub enum DensityType {
+  Grid, // arquivo JSON: {"size": [nx, ny, nz], "data": [...]}
+  Raw, // floats de 32 bits little-endian, com dimensões em "size"
+  Noise
+}
+// formato do arquivo de densidade "Grid"
+#[derive(Deserialize)]
+pub struct DensityGrid {
+  pub size: [usize; 3],
+  pub data: Vec<f32>
+}
+#[derive(Deserialize)]
+pub struct Density {
+  pub kind: DensityType,
+  #[serde(default)]
+  pub file: String,
+  #[serde(default)]
+  pub size: [usize; 3],
+  #[serde(default = "default_one")]
+  pub frequency: f32,
+  #[serde(default = "default_octaves")]
+  pub octaves: u32,
+  #[serde(default)]
+  pub seed: u32
+}
+#[derive(Deserialize, Default)]
+pub enum LightType {
+  #[default]
+  Point,
+  Directional,
+  Spot
 }
 #[derive(Deserialize)]
 pub struct Light {
+  #[serde(default)]
+  pub kind: LightType,
+  // point
+  #[serde(default)]
   pub position: [f32; 3],
+  // directional e spot
+  #[serde(default)]
+  pub direction: [f32; 3],
+  // spot: ângulos do cone em radianos, como o fov
+  #[serde(default)]
+  pub inner_angle: f32,
+  #[serde(default)]
+  pub outer_angle: f32,
+  #[serde(default = "default_one")]
+  pub falloff: f32,
+  pub gobo: Option<String>,
+  // sem `intensity`, as cores abaixo são usadas diretamente e a luz
+  // não enfraquece com a distância (comportamento das cenas antigas)
+  pub intensity: Option<Intensity>,
+  pub attenuation: Option<Attenuation>,
+  // temperatura de cor em Kelvin, que tinge difuse e specular
+  pub temperature: Option<f32>,
+  #[serde(default = "default_white")]
   pub difuse: [f32; 3],
+  #[serde(default = "default_white")]
   pub specular: [f32; 3]
 }
+// potência total emitida ou intensidade luminosa por direção.
+// Para luzes direcionais, ambos são a irradiância que chega à cena
+#[derive(Deserialize, Clone, Copy)]
+pub enum Intensity {
+  Watts(f32),
+  Candela(f32)
+}
+#[derive(Deserialize, Clone, Copy)]
+pub enum Attenuation {
+  None,
+  InverseSquare,
+  // 1 / (constant + linear*d + quadratic*d²)
+  Custom { constant: f32, linear: f32, quadratic: f32 }
+}
 #[derive(Deserialize)]
 pub enum Background {
   Sky,
-  Black
+  Black,
+  Solid([f32; 3]),
+  // cores interpoladas segundo o alinhamento da direção com `axis`:
+  // a posição 0 fica no sentido oposto ao eixo e a 1 no sentido dele
+  Gradient {
+    stops: Vec<GradientStop>,
+    #[serde(default = "default_up")]
+    axis: [f32; 3]
+  },
+  // mapa de ambiente equirretangular (.hdr, .exr, ou uma imagem comum).
+  // Com `samples` > 0, ele também ilumina a cena
+  Environment {
+    file: String,
+    #[serde(default)]
+    rotation: f32,
+    #[serde(default = "default_one")]
+    intensity: f32,
+    #[serde(default)]
+    samples: u32
+  },
+  // céu físico, iluminado por um sol na direção `sun`.
+  // Com `sun_light`, a cena ganha uma luz direcional com a cor do sol
+  SunSky {
+    sun: [f32; 3],
+    #[serde(default = "default_turbidity")]
+    turbidity: f32,
+    #[serde(default = "default_up")]
+    up: [f32; 3],
+    #[serde(default = "default_one")]
+    intensity: f32,
+    #[serde(default)]
+    sun_light: bool
+  }
+}
+#[derive(Deserialize)]
+pub struct GradientStop {
+  pub position: f32,
+  pub color: [f32; 3]
+}
+fn default_turbidity() -> f32 {
+  3.0
+}
+fn default_up() -> [f32; 3] {
+  [0.0, 1.0, 0.0]
 }
 #[derive(Deserialize)]
 pub struct Dof {
@@ -55,6 +290,158 @@ pub struct Dof {
   pub r: f32,
   pub samples: u32
 }
+// amostragem adaptativa: cada pixel recebe entre `min_samples` e
+// `max_samples` raios, parando quando o erro estimado da média fica
+// abaixo de `threshold`. Com `heatmap`, salva também uma imagem com
+// quantas amostras cada pixel usou
+#[derive(Deserialize)]
+pub struct Adaptive {
+  #[serde(default = "default_min_samples")]
+  pub min_samples: u32,
+  #[serde(default = "default_max_samples")]
+  pub max_samples: u32,
+  #[serde(default = "default_noise_threshold")]
+  pub threshold: f32,
+  #[serde(default)]
+  pub heatmap: bool
+}
+impl Adaptive {
+  // (mínimo, máximo) de amostras realmente usados: a estimativa do erro
+  // precisa de ao menos duas, e o máximo não fica abaixo do mínimo
+  pub fn sample_range(&self) -> (u32, u32) {
+    let min_samples = self.min_samples.max(2);
+    (min_samples, self.max_samples.max(min_samples))
+  }
+}
+fn default_min_samples() -> u32 {
+  8
+}
+fn default_max_samples() -> u32 {
+  256
+}
+fn default_noise_threshold() -> f32 {
+  0.01
+}
+#[derive(Deserialize)]
+pub struct Medium {
+  pub absorption: [f32; 3],
+  pub scattering: [f32; 3],
+  #[serde(default)]
+  pub g: f32,
+  #[serde(default = "default_medium_steps")]
+  pub steps: u32,
+  #[serde(default = "default_medium_distance")]
+  pub max_distance: f32
+}
+fn default_medium_steps() -> u32 {
+  32
+}
+fn default_medium_distance() -> f32 {
+  50.0
+}
+#[derive(Deserialize)]
+pub struct Outline {
+  #[serde(default)]
+  pub color: [f32; 3],
+  // diferença relativa de profundidade que gera contorno
+  #[serde(default = "default_depth_threshold")]
+  pub depth_threshold: f32,
+  // cosseno mínimo entre normais vizinhas sem contorno
+  #[serde(default = "default_normal_threshold")]
+  pub normal_threshold: f32
+}
+fn default_depth_threshold() -> f32 {
+  0.1
+}
+fn default_normal_threshold() -> f32 {
+  0.5
+}
+// oclusão ambiente: raios no hemisfério da normal escurecem o termo
+// ambiente quando batem em algo a menos de `max_distance`
+#[derive(Deserialize, Clone, Copy)]
+pub struct AmbientOcclusion {
+  #[serde(default = "default_ao_samples")]
+  pub samples: u32,
+  #[serde(default = "default_one")]
+  pub max_distance: f32
+}
+impl Default for AmbientOcclusion {
+  fn default() -> AmbientOcclusion {
+    AmbientOcclusion {
+      samples: default_ao_samples(),
+      max_distance: 1.0
+    }
+  }
+}
+fn default_ao_samples() -> u32 {
+  16
+}
+// mapa de fótons para cáusticas: `photons` disparados das luzes e os
+// `gather` mais próximos (a até `radius`) usados em cada estimativa
+#[derive(Deserialize)]
+pub struct Caustics {
+  #[serde(default = "default_photons")]
+  pub photons: u32,
+  #[serde(default = "default_gather")]
+  pub gather: usize,
+  #[serde(default = "default_photon_radius")]
+  pub radius: f32
+}
+fn default_photons() -> u32 {
+  100000
+}
+fn default_gather() -> usize {
+  50
+}
+fn default_photon_radius() -> f32 {
+  0.5
+}
+// em cenas com muitas luzes, sorteia `samples` delas por ponto
+// (proporcionalmente à potência) em vez de calcular todas
+#[derive(Deserialize)]
+pub struct LightSampling {
+  #[serde(default = "default_light_samples")]
+  pub samples: u32
+}
+fn default_light_samples() -> u32 {
+  4
+}
+// gerador dos números aleatórios de cada amostra (veja sampler.rs)
+#[derive(Deserialize, Clone, Copy, Default)]
+pub enum SamplerKind {
+  #[default]
+  Independent,
+  Stratified,
+  Halton,
+  Sobol
+}
+// como a cor de cada raio primário é calculada
+#[derive(Deserialize, Clone, Copy, Default)]
+pub enum Integrator {
+  // raios de sombra, reflexão e refração (o renderizador de sempre)
+  #[default]
+  Whitted,
+  // só a oclusão ambiente, em tons de cinza
+  AmbientOcclusion,
+  // traçado de caminhos bidirecional, com `samples` caminhos por pixel
+  // de até `max_depth` reflexões
+  Bidirectional {
+    #[serde(default = "default_bidirectional_samples")]
+    samples: u32,
+    #[serde(default = "default_bidirectional_depth")]
+    max_depth: u32
+  }
+}
+fn default_bidirectional_samples() -> u32 {
+  16
+}
+fn default_bidirectional_depth() -> u32 {
+  5
+}
+#[derive(Deserialize)]
+pub struct Spectral {
+  pub samples: u32
+}
 #[derive(Deserialize)]
 pub struct Scene {
   pub name: String,
@@ -62,10 +449,132 @@ pub struct Scene {
   pub objects: Vec<Object>,
   pub lights: Vec<Light>,
   pub background: Background,
-  pub dof: Dof
+  // salva um PNG com canal alfa, transparente onde só se vê o fundo
+  #[serde(default)]
+  pub transparent: bool,
+  pub dof: Dof,
+  pub adaptive: Option<Adaptive>,
+  pub spectral: Option<Spectral>,
+  pub medium: Option<Medium>,
+  pub outline: Option<Outline>,
+  pub ambient_occlusion: Option<AmbientOcclusion>,
+  #[serde(default)]
+  pub integrator: Integrator,
+  pub caustics: Option<Caustics>,
+  pub light_sampling: Option<LightSampling>,
+  // reflexões e refrações seguidas de cada raio primário
+  #[serde(default = "default_max_depth")]
+  pub max_depth: u32,
+  // raios que contribuem menos que isso para o pixel não são seguidos
+  #[serde(default = "default_min_weight")]
+  pub min_weight: f32,
+  #[serde(default)]
+  pub sampler: SamplerKind,
+  // a mesma semente sempre gera a mesma imagem
+  #[serde(default)]
+  pub seed: u64
+}
+fn default_max_depth() -> u32 {
+  5
+}
+fn default_min_weight() -> f32 {
+  0.001
 }
 
 pub fn parse(contents: String) -> Result<Vec<Scene>> {
-  let scenes:Vec<Scene> = serde_json::from_str(&contents)?;
-  Ok(scenes)
-}
\ No newline at end of file
+  let mut scenes:Vec<Value> = serde_json::from_str(&contents)?;
+  for scene in scenes.iter_mut() {
+    resolve_materials(scene)?;
+  }
+  scenes.into_iter().map(serde_json::from_value).collect()
+}
+
+// Biblioteca de materiais.
+// Cada cena pode ter um mapa "materials" (nome -> material) e/ou apontar
+// um arquivo externo com o mesmo formato em "material_library". Os
+// materiais da cena têm prioridade sobre os do arquivo.
+// O material de um objeto pode então ser:
+//  - um bloco completo, como antes;
+//  - uma string com o nome de um material da biblioteca;
+//  - um bloco com "base": nome, cujos demais campos sobrescrevem os do material base.
+fn resolve_materials(scene: &mut Value) -> Result<()> {
+  let mut library = Map::new();
+  if let Some(file) = scene.get("material_library").and_then(Value::as_str) {
+    let contents = fs::read_to_string(file).map_err(|err| {
+      serde_json::Error::custom(format!("erro ao ler biblioteca de materiais {}: {}", file, err))
+    })?;
+    let materials: Map<String, Value> = serde_json::from_str(&contents)?;
+    library.extend(materials);
+  }
+  if let Some(Value::Object(materials)) = scene.get("materials") {
+    library.extend(materials.clone());
+  }
+  if let Some(Value::Array(objects)) = scene.get_mut("objects") {
+    for object in objects.iter_mut() {
+      if let Some(material) = object.get_mut("material") {
+        *material = resolve_material(material, &library)?;
+      }
+    }
+  }
+  Ok(())
+}
+fn library_material(name: &str, library: &Map<String, Value>) -> Result<Value> {
+  library.get(name).cloned().ok_or_else(|| {
+    serde_json::Error::custom(format!("material desconhecido: {}", name))
+  })
+}
+fn resolve_material(material: &Value, library: &Map<String, Value>) -> Result<Value> {
+  match material {
+    Value::String(name) => library_material(name, library),
+    Value::Object(fields) => match fields.get("base").and_then(Value::as_str) {
+      Some(name) => {
+        let mut resolved = library_material(name, library)?;
+        if let Value::Object(base) = &mut resolved {
+          for (key, value) in fields.iter().filter(|(key, _)| *key != "base") {
+            base.insert(key.clone(), value.clone());
+          }
+        }
+        Ok(resolved)
+      },
+      None => Ok(material.clone())
+    },
+    _ => Ok(material.clone())
+  }
+}
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  fn library() -> Map<String, Value> {
+    match json!({ "vidro": { "rgb": [1.0, 1.0, 1.0], "kt": 0.9, "kr": 1.5 } }) {
+      Value::Object(library) => library,
+      _ => unreachable!()
+    }
+  }
+
+  #[test]
+  fn name_resolves_to_library_material() {
+    let material = resolve_material(&json!("vidro"), &library()).unwrap();
+    assert_eq!(material, json!({ "rgb": [1.0, 1.0, 1.0], "kt": 0.9, "kr": 1.5 }));
+  }
+
+  #[test]
+  fn unknown_name_is_an_error() {
+    let err = resolve_material(&json!("madeira"), &library()).unwrap_err();
+    assert!(err.to_string().contains("material desconhecido: madeira"));
+    assert!(resolve_material(&json!({ "base": "madeira", "kt": 0.5 }), &library()).is_err());
+  }
+
+  #[test]
+  fn base_fields_are_overridden() {
+    let material = resolve_material(&json!({ "base": "vidro", "rgb": [1.0, 0.5, 0.2], "ks": 0.1 }), &library()).unwrap();
+    assert_eq!(material, json!({ "rgb": [1.0, 0.5, 0.2], "kt": 0.9, "kr": 1.5, "ks": 0.1 }));
+  }
+
+  #[test]
+  fn full_material_is_kept() {
+    let material = json!({ "rgb": [0.2, 0.2, 0.2], "kd": 0.8 });
+    assert_eq!(resolve_material(&material, &library()).unwrap(), material);
+  }
+}
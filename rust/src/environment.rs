@@ -0,0 +1,155 @@
+use super::base::{Vector, Color};
+use image::hdr::HDRDecoder;
+use std::f32::consts::PI;
+use std::fs::File;
+use std::io::BufReader;
+
+// mapa de ambiente equirretangular: u é o azimute em torno do eixo y
+// e v vai do topo (+y) até a base (-y) da imagem
+pub struct Environment {
+  width: usize,
+  height: usize,
+  pixels: Vec<Color>,
+  rotation: f32, // em radianos, em torno do eixo y
+  intensity: f32,
+  pub samples: u32, // direções amostradas por ponto para iluminar a cena
+  // distribuições acumuladas para amostragem por importância:
+  // uma sobre as linhas e, para cada linha, uma sobre as colunas
+  marginal: Vec<f32>,
+  conditional: Vec<f32>
+}
+impl Environment {
+  pub fn open(path: &str, rotation: f32, intensity: f32, samples: u32) -> Result<Environment, String> {
+    let lower = path.to_lowercase();
+    let (width, height, pixels) = if lower.ends_with(".hdr") {
+      let file = File::open(path).map_err(|err| err.to_string())?;
+      let decoder = HDRDecoder::new(BufReader::new(file)).map_err(|err| err.to_string())?;
+      let metadata = decoder.metadata();
+      let pixels = decoder.read_image_hdr().map_err(|err| err.to_string())?
+        .iter()
+        .map(|pixel| Color::from(pixel.data[0], pixel.data[1], pixel.data[2]))
+        .collect();
+      (metadata.width as usize, metadata.height as usize, pixels)
+    } else if lower.ends_with(".exr") {
+      // o crate `image` desta versão não decodifica OpenEXR; a primeira
+      // camada RGB(A) é lida pelo crate `exr`
+      let image = exr::prelude::read_first_rgba_layer_from_file(
+        path,
+        |resolution, _| (resolution.width(), (0..resolution.area()).map(|_| Color::black()).collect()),
+        |(width, pixels): &mut (usize, Vec<Color>), position, (r, g, b, _): (f32, f32, f32, f32)| {
+          pixels[position.y() * *width + position.x()] = Color::from(r, g, b);
+        }
+      ).map_err(|err| err.to_string())?;
+      let size = image.layer_data.size;
+      let (_, pixels) = image.layer_data.channel_data.pixels;
+      (size.width(), size.height(), pixels)
+    } else {
+      // imagens comuns (png, jpg...) também servem, sem alcance dinâmico
+      let img = image::open(path).map_err(|err| err.to_string())?.to_rgb();
+      let (width, height) = img.dimensions();
+      let pixels = img.pixels()
+        .map(|pixel| {
+          let [r, g, b] = pixel.data;
+          Color::from(r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0)
+        })
+        .collect();
+      (width as usize, height as usize, pixels)
+    };
+    if width == 0 || height == 0 {
+      return Err("imagem vazia".to_string());
+    }
+    let mut environment = Environment {
+      width, height, pixels, rotation, intensity, samples,
+      marginal: Vec::new(),
+      conditional: Vec::new()
+    };
+    environment.build_distribution();
+    Ok(environment)
+  }
+  // o peso de cada pixel é o seu brilho vezes o seno da latitude,
+  // já que as linhas perto dos polos cobrem um ângulo sólido menor.
+  // O pequeno acréscimo mantém a pdf positiva onde o mapa é preto
+  fn build_distribution(&mut self) {
+    let (w, h) = (self.width, self.height);
+    self.conditional = vec![0.0; h * (w + 1)];
+    self.marginal = vec![0.0; h + 1];
+    for y in 0..h {
+      let sin_theta = (PI * (y as f32 + 0.5) / h as f32).sin();
+      let row = &mut self.conditional[y * (w + 1)..(y + 1) * (w + 1)];
+      for x in 0..w {
+        let weight = (self.pixels[y * w + x].luminance().max(0.0) + 1e-4) * sin_theta;
+        row[x + 1] = row[x] + weight;
+      }
+      self.marginal[y + 1] = self.marginal[y] + row[w];
+    }
+  }
+  fn to_local(&self, direction: &Vector) -> Vector {
+    let (sin, cos) = (-self.rotation).sin_cos();
+    Vector::from(direction.x * cos + direction.z * sin, direction.y, -direction.x * sin + direction.z * cos)
+  }
+  fn to_world(&self, local: &Vector) -> Vector {
+    let (sin, cos) = self.rotation.sin_cos();
+    Vector::from(local.x * cos + local.z * sin, local.y, -local.x * sin + local.z * cos)
+  }
+  fn pixel_at(&self, direction: &Vector) -> (usize, usize) {
+    let d = self.to_local(&direction.normalized());
+    let u = 0.5 + d.x.atan2(-d.z) / (2.0 * PI);
+    let v = d.y.clamp(-1.0, 1.0).acos() / PI;
+    let x = ((u * self.width as f32) as usize).min(self.width - 1);
+    let y = ((v * self.height as f32) as usize).min(self.height - 1);
+    (x, y)
+  }
+  pub fn radiance(&self, direction: &Vector) -> Color {
+    let (x, y) = self.pixel_at(direction);
+    &self.pixels[y * self.width + x] * self.intensity
+  }
+  // densidade de probabilidade (por ângulo sólido) de `sample` gerar `direction`
+  pub fn pdf(&self, direction: &Vector) -> f32 {
+    let (x, y) = self.pixel_at(direction);
+    let row = &self.conditional[y * (self.width + 1)..];
+    let weight = row[x + 1] - row[x];
+    let sin_theta = (PI * (y as f32 + 0.5) / self.height as f32).sin();
+    let pdf_uv = weight / self.marginal[self.height] * (self.width * self.height) as f32;
+    pdf_uv / (2.0 * PI * PI * sin_theta.max(1e-4))
+  }
+  // escolhe uma direção proporcionalmente ao brilho do mapa.
+  // Retorna a direção, a radiância que vem dela e a pdf
+  pub fn sample(&self, u1: f32, u2: f32) -> (Vector, Color, f32) {
+    let y = find_interval(&self.marginal, u1 * self.marginal[self.height]);
+    let row = &self.conditional[y * (self.width + 1)..(y + 1) * (self.width + 1)];
+    let x = find_interval(row, u2 * row[self.width]);
+    // posição contínua dentro do pixel escolhido
+    let fy = (u1 * self.marginal[self.height] - self.marginal[y]) / (self.marginal[y + 1] - self.marginal[y]);
+    let fx = (u2 * row[self.width] - row[x]) / (row[x + 1] - row[x]);
+    let u = (x as f32 + fx.clamp(0.0, 1.0)) / self.width as f32;
+    let v = (y as f32 + fy.clamp(0.0, 1.0)) / self.height as f32;
+    let theta = v * PI;
+    let phi = (u - 0.5) * 2.0 * PI;
+    let local = Vector::from(theta.sin() * phi.sin(), theta.cos(), -theta.sin() * phi.cos());
+    let direction = self.to_world(&local);
+    let radiance = self.radiance(&direction);
+    let pdf = self.pdf(&direction);
+    (direction, radiance, pdf)
+  }
+}
+// índice i tal que cdf[i] <= value < cdf[i + 1]
+fn find_interval(cdf: &[f32], value: f32) -> usize {
+  let last = cdf.len() - 2;
+  let i = cdf.partition_point(|&c| c <= value);
+  i.saturating_sub(1).min(last)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn exr_keeps_values_above_one() {
+    let path = std::env::temp_dir().join("environment_test.exr");
+    exr::prelude::write_rgb_file(&path, 4, 2, |x, y| (x as f32 * 2.0, y as f32, 0.5)).unwrap();
+    let environment = Environment::open(path.to_str().unwrap(), 0.0, 1.0, 0).unwrap();
+    assert_eq!((environment.width, environment.height), (4, 2));
+    let pixel = &environment.pixels[4 + 3];
+    assert_eq!((pixel.r, pixel.g, pixel.b), (6.0, 1.0, 0.5));
+  }
+}
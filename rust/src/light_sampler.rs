@@ -0,0 +1,56 @@
+use super::objets::{Light, LightKind};
+
+// Escolhe luzes ao acaso com probabilidade proporcional à potência, para
+// que cada ponto calcule só algumas delas. Dividir a contribuição de cada
+// luz sorteada pela probabilidade de sorteá-la mantém a média correta.
+// Luzes direcionais não entram no sorteio e são sempre calculadas, assim
+// como todas as luzes quando não há mais delas do que amostras
+pub struct LightSampler {
+  pub always: Vec<usize>, // índices das luzes sempre calculadas
+  sampled: Vec<usize>, // índices das luzes sorteadas
+  cdf: Vec<f32>, // distribuição acumulada das potências, de 0 a 1
+  pub samples: u32 // sorteios por ponto
+}
+impl LightSampler {
+  pub fn new(lights: &[Light], samples: u32) -> LightSampler {
+    let samples = samples.max(1);
+    let mut always = Vec::new();
+    let mut sampled = Vec::new();
+    for (i, light) in lights.iter().enumerate() {
+      match light.kind {
+        LightKind::Directional { .. } => always.push(i),
+        _ => sampled.push(i)
+      }
+    }
+    let powers: Vec<f32> = sampled.iter().map(|&i| power(&lights[i])).collect();
+    let total: f32 = powers.iter().sum();
+    if sampled.len() <= samples as usize || total <= 0.0 {
+      always.append(&mut sampled);
+    }
+    let mut cdf = Vec::with_capacity(sampled.len());
+    let mut sum = 0.0;
+    for power in powers.iter().take(sampled.len()) {
+      sum += power / total;
+      cdf.push(sum);
+    }
+    LightSampler { always, sampled, cdf, samples }
+  }
+  pub fn is_empty(&self) -> bool {
+    self.sampled.is_empty()
+  }
+  // índice da luz sorteada por `u` (em [0, 1)) e a probabilidade de sorteá-la
+  pub fn sample(&self, u: f32) -> (usize, f32) {
+    let i = self.cdf.partition_point(|&c| c <= u).min(self.cdf.len() - 1);
+    let previous = if i > 0 { self.cdf[i - 1] } else { 0.0 };
+    (self.sampled[i], self.cdf[i] - previous)
+  }
+}
+// potência aproximada de uma luz: o brilho da cor vezes a intensidade,
+// proporcional à fração da esfera que um spot ilumina
+fn power(light: &Light) -> f32 {
+  let brightness = (&light.difuse + &light.specular).luminance().max(0.0) * light.intensity;
+  match &light.kind {
+    LightKind::Spot { outer, .. } => brightness * (1.0 - outer.cos()) / 2.0,
+    _ => brightness
+  }
+}
@@ -1,12 +1,28 @@
 mod base;
 use base::{Vector, Color, Point};
 mod objets;
-use objets::{Camera, Material, Plane, Sphere, Object, Light};
-use image::{ImageBuffer, Rgb};
+use objets::{Camera, Material, Plane, Sphere, Volume, Object, Light, LightKind, Attenuation, Integrator};
+use image::{ImageBuffer, Rgb, Rgba};
 use image;
 mod config;
+mod textures;
+mod brdf;
+mod spectral;
+mod media;
+mod environment;
+use environment::Environment;
+mod sky;
+use sky::Sky;
+mod photons;
+use photons::{PhotonMap, Caustics};
+mod bidirectional;
+mod light_sampler;
+use light_sampler::LightSampler;
+mod sampler;
+use textures::{Texture, ImageTexture, Perlin, Checker, Noise, Turbulence, Marble, Wood};
 use std::process::exit;
 use std::fs;
+use std::f32::consts::PI;
 
 fn main() {
   let contents = fs::read_to_string("scenes.json").unwrap_or_else(|err| {
@@ -21,8 +37,28 @@ fn main() {
     render_scene(scene);
   }
 }
-fn render_scene(scene:config::Scene) {
+fn render_scene(mut scene:config::Scene) {
   println!("Renderizando cena {}", scene.name);
+  sampler::install(scene.sampler, scene.seed);
+  if let config::Background::Gradient { stops, .. } = &mut scene.background {
+    stops.sort_by(|a, b| a.position.partial_cmp(&b.position).unwrap_or(std::cmp::Ordering::Equal));
+  }
+  let environment = match &scene.background {
+    config::Background::Environment { file, rotation, intensity, samples } => {
+      let environment = Environment::open(file, *rotation, *intensity, *samples).unwrap_or_else(|err| {
+        eprintln!("Erro ao carregar mapa de ambiente {}: {}", file, err);
+        exit(1);
+      });
+      Some(environment)
+    },
+    _ => None
+  };
+  let sky = match &scene.background {
+    config::Background::SunSky { sun, turbidity, up, intensity, .. } => {
+      Some(Sky::new(&Vector::from_array(sun), &Vector::from_array(up), *turbidity, *intensity))
+    },
+    _ => None
+  };
   let mut camera = Camera {
     xyz: Point::from_array(&scene.camera.position),
     target: Vector::from(0.0, 0.0, 0.0),
@@ -32,7 +68,19 @@ fn render_scene(scene:config::Scene) {
     image_plane_distance: 0.0,
     width: scene.camera.width,
     height: scene.camera.height,
-    background: scene.background
+    background: scene.background,
+    environment,
+    sky,
+    transparent: scene.transparent,
+    spectrum: scene.spectral.map(|spectral| spectral::Spectrum::new(spectral.samples.max(1))),
+    medium: scene.medium.as_ref().map(build_medium),
+    outline: scene.outline,
+    ambient_occlusion: scene.ambient_occlusion,
+    integrator: scene.integrator,
+    caustics: None,
+    light_sampler: None,
+    max_depth: scene.max_depth,
+    min_weight: scene.min_weight
   };
   camera.set_target(Point::from_array(&scene.camera.target));
   if scene.camera.fov > 0.0 {
@@ -56,55 +104,268 @@ fn render_scene(scene:config::Scene) {
   for object in scene.objects {
     match object.kind {
       config::ObjectType::Plane => {
-        let material = Material {
-          rgb: Color::from_array(&object.material.rgb),
-          kd: object.material.kd,
-          ks: object.material.ks,
-          kt: object.material.kt,
-          kr: object.material.kr,
-          alpha: object.material.alpha
-        };
+        let material = build_material(&object.material);
         let obj = Plane::from(material, Vector::from_array(&object.normal), &Point::from_array(&object.point));
         objects.push(Box::new(obj));
       },
       config::ObjectType::Sphere => {
-        let material = Material {
-          rgb: Color::from_array(&object.material.rgb),
-          kd: object.material.kd,
-          ks: object.material.ks,
-          kt: object.material.kt,
-          kr: object.material.kr,
-          alpha: object.material.alpha
-        };
+        let material = build_material(&object.material);
         let obj = Sphere::from(material, object.r, Point::from_array(&object.center));
         objects.push(Box::new(obj));
+      },
+      config::ObjectType::Volume => {
+        let material = build_material(&object.material);
+        let density = match &object.density {
+          Some(density) => build_density(density),
+          None => {
+            eprintln!("Erro: volumes precisam de uma densidade (\"density\")");
+            exit(2);
+          }
+        };
+        let obj = Volume::from(
+          material,
+          Point::from_array(&object.min),
+          Point::from_array(&object.max),
+          density,
+          object.density_scale,
+          object.g
+        );
+        objects.push(Box::new(obj));
       }
     }
   }
   for light in scene.lights {
+    let needs_direction = match light.kind {
+      config::LightType::Point => false,
+      config::LightType::Directional | config::LightType::Spot => true
+    };
+    if needs_direction && light.direction == [0.0, 0.0, 0.0] {
+      eprintln!("Erro: luzes direcionais e spot precisam de uma direção (\"direction\")");
+      exit(2);
+    }
+    if let config::LightType::Spot = light.kind {
+      if light.outer_angle <= 0.0 {
+        eprintln!("Erro: luzes spot precisam de um ângulo de abertura (\"outer_angle\")");
+        exit(2);
+      }
+    }
+    let kind = match light.kind {
+      config::LightType::Point => LightKind::Point,
+      config::LightType::Directional => LightKind::Directional {
+        direction: Vector::from_array(&light.direction)
+      },
+      config::LightType::Spot => LightKind::Spot {
+        direction: Vector::from_array(&light.direction),
+        inner: light.inner_angle,
+        outer: light.outer_angle.max(light.inner_angle),
+        falloff: light.falloff,
+        gobo: light.gobo.as_ref().map(|file| {
+          ImageTexture::open(file, config::WrapMode::Clamp, [1.0, 1.0]).unwrap_or_else(|err| {
+            eprintln!("Erro ao carregar gobo {}: {}", file, err);
+            exit(1);
+          })
+        })
+      }
+    };
+    let tint = match light.temperature {
+      Some(kelvin) => spectral::blackbody(kelvin),
+      None => Color::white()
+    };
+    let intensity = match light.intensity {
+      None => 1.0,
+      Some(config::Intensity::Candela(candela)) => candela,
+      // potência espalhada pelo ângulo sólido que a luz ilumina
+      Some(config::Intensity::Watts(watts)) => match &kind {
+        LightKind::Point => watts / (4.0 * PI),
+        LightKind::Spot { inner, outer, .. } => {
+          watts / (2.0 * PI * (1.0 - 0.5 * (inner.cos() + outer.cos())))
+        },
+        LightKind::Directional { .. } => watts
+      }
+    };
+    let attenuation = match (light.attenuation, light.intensity) {
+      (Some(attenuation), _) => attenuation,
+      (None, Some(_)) => Attenuation::InverseSquare,
+      (None, None) => Attenuation::None
+    };
     lights.push(Light {
+      kind,
       xyz: Point::from_array(&light.position),
-      difuse: Color::from_array(&light.difuse),
-      specular: Color::from_array(&light.specular)
+      difuse: Color::from_array(&light.difuse) * &tint,
+      specular: Color::from_array(&light.specular) * &tint,
+      intensity,
+      attenuation
     })
   }
+  // o sol que acompanha o céu físico
+  if let (config::Background::SunSky { sun, sun_light: true, .. }, Some(sky)) = (&camera.background, &camera.sky) {
+    let color = sky.sun_color();
+    lights.push(Light {
+      kind: LightKind::Directional { direction: -Vector::from_array(sun) },
+      xyz: Point::from(0.0, 0.0, 0.0),
+      difuse: color.clone(),
+      specular: color,
+      intensity: 1.0,
+      attenuation: Attenuation::None
+    });
+  }
   
+  if let Some(light_sampling) = &scene.light_sampling {
+    camera.light_sampler = Some(LightSampler::new(&lights, light_sampling.samples));
+  }
+  if let Some(caustics) = &scene.caustics {
+    let map = PhotonMap::build(camera.trace_caustics(&objects, &lights, caustics.photons));
+    println!("  {} fótons de cáustica", map.len());
+    camera.caustics = Some(Caustics {
+      map,
+      gather: caustics.gather.max(1),
+      radius: caustics.radius
+    });
+  }
+
   let filename = (scene.name+".png").to_string();
-  let mut pixels:Vec<Vec<[u8; 3]>>;
-  if scene.dof.active {
+  let mut pixels:Vec<Vec<[u8; 4]>>;
+  if let Integrator::Bidirectional { samples, max_depth } = scene.integrator {
+    pixels = camera.take_bidirectional_picture(&objects, &lights, samples, max_depth);
+  }
+  else if let Some(adaptive) = &scene.adaptive {
+    let lens = if scene.dof.active { Some((scene.dof.r, scene.dof.focal_length)) } else { None };
+    let (colors, counts) = camera.take_adaptive_picture(&objects, &lights, adaptive, lens);
+    let total: u64 = counts.iter().flatten().map(|&n| n as u64).sum();
+    println!("  média de {:.1} amostras por pixel", total as f64 / (camera.width * camera.height) as f64);
+    if adaptive.heatmap {
+      let heatmap_name = format!("{}_amostras.png", filename.trim_end_matches(".png"));
+      let (min_samples, max_samples) = adaptive.sample_range();
+      save_image(heatmap(&counts, min_samples, max_samples), &heatmap_name, false);
+      println!("Mapa de amostras salvo em {}", heatmap_name);
+    }
+    pixels = colors;
+  }
+  else if scene.dof.active {
     pixels = camera.take_dof_picture(&objects, &lights, scene.dof.r, scene.dof.focal_length, scene.dof.samples);
   }
   else {
     pixels = camera.take_picture(&objects, &lights);
   }
-  save_image(pixels, &filename);
+  save_image(pixels, &filename, scene.transparent);
   println!("Salvo em {}", filename);
 }
-fn save_image(pixels: Vec<Vec<[u8; 3]>>, name: &String) {
+fn build_material(material: &config::Material) -> Material {
+  Material {
+    rgb: Color::from_array(&material.rgb),
+    kd: material.kd,
+    ks: material.ks,
+    kt: material.kt,
+    kr: material.kr,
+    dispersion: material.dispersion,
+    alpha: material.alpha,
+    model: material.model,
+    metallic: material.metallic,
+    roughness: material.roughness,
+    glossy_samples: material.glossy_samples,
+    bands: material.bands,
+    absorption: Color::from_array(&material.absorption),
+    density: material.density,
+    medium: material.medium.as_ref().map(build_medium),
+    texture: material.texture.as_ref().map(build_texture),
+    kd_texture: material.kd_texture.as_ref().map(build_texture),
+    ks_texture: material.ks_texture.as_ref().map(build_texture),
+    kt_texture: material.kt_texture.as_ref().map(build_texture),
+    normal_map: material.normal_map.as_ref().map(build_texture),
+    bump: material.bump.as_ref().map(build_texture),
+    bump_strength: material.bump_strength
+  }
+}
+fn build_density(density: &config::Density) -> media::DensityField {
+  let field = match density.kind {
+    config::DensityType::Grid => media::DensityField::from_json(&density.file),
+    config::DensityType::Raw => media::DensityField::from_raw(&density.file, density.size),
+    config::DensityType::Noise => Ok(media::DensityField::Noise {
+      perlin: Box::new(Perlin::new(density.seed)),
+      frequency: density.frequency,
+      octaves: density.octaves
+    })
+  };
+  field.unwrap_or_else(|err| {
+    eprintln!("Erro ao carregar densidade {}: {}", density.file, err);
+    exit(1);
+  })
+}
+fn build_medium(medium: &config::Medium) -> media::Medium {
+  media::Medium {
+    absorption: Color::from_array(&medium.absorption),
+    scattering: Color::from_array(&medium.scattering),
+    g: medium.g,
+    steps: medium.steps,
+    max_distance: medium.max_distance
+  }
+}
+fn build_texture(texture: &config::Texture) -> Box<dyn Texture> {
+  let colors = [Color::from_array(&texture.colors[0]), Color::from_array(&texture.colors[1])];
+  match texture.kind {
+    config::TextureType::Image => {
+      let image = ImageTexture::open(&texture.file, texture.wrap, texture.scale).unwrap_or_else(|err| {
+        eprintln!("Erro ao carregar textura {}: {}", texture.file, err);
+        exit(1);
+      });
+      Box::new(image)
+    },
+    config::TextureType::Checker => Box::new(Checker {
+      colors,
+      frequency: texture.frequency
+    }),
+    config::TextureType::Noise => Box::new(Noise {
+      colors,
+      frequency: texture.frequency,
+      perlin: Perlin::new(texture.seed)
+    }),
+    config::TextureType::Turbulence => Box::new(Turbulence {
+      colors,
+      frequency: texture.frequency,
+      octaves: texture.octaves,
+      perlin: Perlin::new(texture.seed)
+    }),
+    config::TextureType::Marble => Box::new(Marble {
+      colors,
+      frequency: texture.frequency,
+      octaves: texture.octaves,
+      strength: texture.strength,
+      perlin: Perlin::new(texture.seed)
+    }),
+    config::TextureType::Wood => Box::new(Wood {
+      colors,
+      frequency: texture.frequency,
+      octaves: texture.octaves,
+      strength: texture.strength,
+      perlin: Perlin::new(texture.seed)
+    })
+  }
+}
+// mapa de calor das amostras por pixel: preto no mínimo, passando
+// por vermelho e amarelo, até branco no máximo
+fn heatmap(counts: &[Vec<u32>], min_samples: u32, max_samples: u32) -> Vec<Vec<[u8; 4]>> {
+  let range = max_samples.saturating_sub(min_samples).max(1) as f32;
+  counts.iter().map(|column| column.iter().map(|&n| {
+    let t = (n.saturating_sub(min_samples) as f32 / range).clamp(0.0, 1.0) * 3.0;
+    Color::from(t.clamp(0.0, 1.0), (t - 1.0).clamp(0.0, 1.0), (t - 2.0).clamp(0.0, 1.0)).to_rgba(1.0)
+  }).collect()).collect()
+}
+fn save_image(pixels: Vec<Vec<[u8; 4]>>, name: &String, transparent: bool) {
+  if transparent {
+    let mut image = ImageBuffer::<Rgba<u8>, Vec<u8>>::new(pixels.len() as u32, pixels[0].len() as u32);
+    for (x, column) in pixels.iter().enumerate() {
+      for (y, pixel) in column.iter().enumerate() {
+        image.get_pixel_mut(x as u32, y as u32).data = *pixel;
+      }
+    }
+    image.save(name).unwrap();
+    return;
+  }
   let mut image = ImageBuffer::<Rgb<u8>, Vec<u8>>::new(pixels.len() as u32, pixels[0].len() as u32);
   for x in 0..pixels.len() {
     for y in 0..pixels[0].len() {
-      image.get_pixel_mut(x as u32, y as u32).data = pixels[x][y]; 
+      let [r, g, b, _] = pixels[x][y];
+      image.get_pixel_mut(x as u32, y as u32).data = [r, g, b]; 
     }
   }
   image.get_pixel_mut(0, 0).data = [0, 0, 255];
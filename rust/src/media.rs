@@ -0,0 +1,100 @@
+use super::base::{Vector, Point, Color};
+use super::textures::Perlin;
+use super::config::DensityGrid;
+use std::fs;
+
+// meio participante homogêneo (névoa, fumaça rala, água turva...)
+pub struct Medium {
+  pub absorption: Color, // coeficiente de absorção (por unidade de distância)
+  pub scattering: Color, // coeficiente de espalhamento
+  pub g: f32, // anisotropia de Henyey-Greenstein: -1 (para trás) a 1 (para frente)
+  pub steps: u32, // passos da integração ao longo do raio
+  pub max_distance: f32 // até onde integrar raios que não atingem nada
+}
+impl Medium {
+  pub fn extinction(&self) -> Color {
+    &self.absorption + &self.scattering
+  }
+  pub fn transmittance(&self, distance: f32) -> Color {
+    let e = self.extinction();
+    Color::from(
+      (-e.r * distance).exp(),
+      (-e.g * distance).exp(),
+      (-e.b * distance).exp()
+    )
+  }
+  pub fn phase(&self, direction: &Vector, light_direction: &Vector) -> f32 {
+    henyey_greenstein(self.g, direction, light_direction)
+  }
+}
+// função de fase de Henyey-Greenstein, multiplicada por 4π para que o
+// caso isotrópico valha 1 (mesma escala das luzes no resto do renderizador).
+// `direction` é a direção do raio e `light_direction` aponta para a luz
+pub fn henyey_greenstein(g: f32, direction: &Vector, light_direction: &Vector) -> f32 {
+  let cos_theta = direction.dot(light_direction);
+  let denom = 1.0 + g*g - 2.0*g*cos_theta;
+  (1.0 - g*g) / (denom * denom.sqrt())
+}
+
+// densidade de um volume heterogêneo, em coordenadas locais [0, 1]³
+pub enum DensityField {
+  // grade densa de voxels, com x variando mais rápido
+  Grid { size: [usize; 3], data: Vec<f32> },
+  Noise { perlin: Box<Perlin>, frequency: f32, octaves: u32 }
+}
+impl DensityField {
+  // {"size": [nx, ny, nz], "data": [...]}
+  pub fn from_json(path: &str) -> Result<DensityField, String> {
+    let contents = fs::read_to_string(path).map_err(|err| err.to_string())?;
+    let grid: DensityGrid = serde_json::from_str(&contents).map_err(|err| err.to_string())?;
+    DensityField::grid(grid.size, grid.data)
+  }
+  // floats de 32 bits little-endian, sem cabeçalho
+  pub fn from_raw(path: &str, size: [usize; 3]) -> Result<DensityField, String> {
+    let bytes = fs::read(path).map_err(|err| err.to_string())?;
+    let data = bytes.chunks_exact(4)
+      .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+      .collect();
+    DensityField::grid(size, data)
+  }
+  fn grid(size: [usize; 3], data: Vec<f32>) -> Result<DensityField, String> {
+    let expected = size[0] * size[1] * size[2];
+    if expected == 0 || data.len() != expected {
+      return Err(format!("a grade deveria ter {} valores, mas tem {}", expected, data.len()));
+    }
+    Ok(DensityField::Grid { size, data })
+  }
+  // maior densidade possível (majorante usado no delta tracking)
+  pub fn max(&self) -> f32 {
+    match self {
+      DensityField::Grid { data, .. } => data.iter().cloned().fold(0.0, f32::max),
+      DensityField::Noise { .. } => 1.0
+    }
+  }
+  pub fn density(&self, local: &Point) -> f32 {
+    match self {
+      DensityField::Grid { size, data } => {
+        // interpolação trilinear entre os centros dos voxels
+        let coord = |v: f32, n: usize| {
+          let x = (v * n as f32 - 0.5).max(0.0).min(n as f32 - 1.0);
+          let i = (x.floor() as usize).min(n - 1);
+          let j = (i + 1).min(n - 1);
+          (i, j, x - i as f32)
+        };
+        let (x0, x1, fx) = coord(local.x, size[0]);
+        let (y0, y1, fy) = coord(local.y, size[1]);
+        let (z0, z1, fz) = coord(local.z, size[2]);
+        let at = |x: usize, y: usize, z: usize| data[(z * size[1] + y) * size[0] + x];
+        let lerp = |a: f32, b: f32, t: f32| a + t * (b - a);
+        lerp(
+          lerp(lerp(at(x0, y0, z0), at(x1, y0, z0), fx), lerp(at(x0, y1, z0), at(x1, y1, z0), fx), fy),
+          lerp(lerp(at(x0, y0, z1), at(x1, y0, z1), fx), lerp(at(x0, y1, z1), at(x1, y1, z1), fx), fy),
+          fz
+        )
+      },
+      DensityField::Noise { perlin, frequency, octaves } => {
+        perlin.turbulence(&(local * *frequency), *octaves).min(1.0)
+      }
+    }
+  }
+}
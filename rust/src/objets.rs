@@ -1,10 +1,20 @@
 use super::base::{Vector,Point,Color};
+use super::textures::{Texture, ImageTexture};
+use super::brdf;
+use super::spectral::Spectrum;
+use super::environment::Environment;
+use super::sky::Sky;
+use super::photons::{Photon, Caustics};
+use super::light_sampler::LightSampler;
+use super::sampler;
+use super::media::{Medium, DensityField, henyey_greenstein};
 use rand::Rng;
-pub use super::config::Background;
+pub use super::config::{Background, GradientStop, ShadingModel, Dispersion, Outline, Attenuation, AmbientOcclusion, Integrator, Adaptive};
 
 pub struct Ray {
-  origin: Point,
-  direction: Vector
+  pub origin: Point,
+  pub direction: Vector,
+  pub wavelength: Option<f32> // em nm; só no modo espectral
 }
 impl Ray {
   pub fn point_at(&self, t:f32) -> Point {
@@ -18,14 +28,130 @@ pub struct Material {
   pub ks: f32, // fator especular
   pub kt: f32, // fator de transmissão
   pub kr: f32, // fator reflexivo (ar = 1, vidro = 1.25)
-  pub alpha: f32 // rugosidade
+  pub dispersion: Option<Dispersion>, // variação de kr com o comprimento de onda
+  pub alpha: f32, // rugosidade (expoente de Phong)
+  pub model: ShadingModel,
+  pub metallic: f32, // só no modelo GGX
+  pub roughness: f32, // GGX e reflexões/refrações foscas
+  pub bands: u32, // faixas de iluminação do modelo Toon
+  pub glossy_samples: u32, // raios por reflexão/refração fosca
   // faltando o ke. O que ele faz?
+  pub absorption: Color, // cor que sobrevive a uma unidade de distância dentro do objeto
+  pub density: f32, // quão rápido a absorção acontece (0 = sem absorção)
+  pub medium: Option<Medium>, // meio participante no interior do objeto
+  pub texture: Option<Box<dyn Texture>>, // se existir, substitui o rgb
+  // se existirem, multiplicam os respectivos coeficientes
+  pub kd_texture: Option<Box<dyn Texture>>,
+  pub ks_texture: Option<Box<dyn Texture>>,
+  pub kt_texture: Option<Box<dyn Texture>>,
+  // perturbações da normal: mapa de normais no espaço tangente
+  // ou uma textura escalar usada como altura (bump)
+  pub normal_map: Option<Box<dyn Texture>>,
+  pub bump: Option<Box<dyn Texture>>,
+  pub bump_strength: f32
+}
+fn textured(value: f32, texture: &Option<Box<dyn Texture>>, uv: (f32, f32), point: &Point) -> f32 {
+  match texture {
+    Some(texture) => value * texture.value_at(uv, point),
+    None => value
+  }
+}
+impl Material {
+  // índice de refração para o comprimento de onda dado.
+  // kr é o índice na linha D do sódio (589.3nm)
+  pub fn ior(&self, wavelength: Option<f32>) -> f32 {
+    match (&self.dispersion, wavelength) {
+      (Some(Dispersion::Cauchy { b }), Some(wavelength)) => {
+        let l = wavelength / 1000.0; // em micrômetros
+        let d = 0.5893;
+        self.kr + b * (1.0/(l*l) - 1.0/(d*d))
+      },
+      (Some(Dispersion::Sellmeier { b, c }), Some(wavelength)) => {
+        let l2 = (wavelength / 1000.0).powi(2);
+        let mut n2 = 1.0;
+        for i in 0..3 {
+          n2 += b[i] * l2 / (l2 - c[i]);
+        }
+        n2.sqrt()
+      },
+      _ => self.kr
+    }
+  }
+  pub fn color_at(&self, uv: (f32, f32), point: &Point) -> Color {
+    match &self.texture {
+      Some(texture) => texture.color_at(uv, point),
+      None => self.rgb.clone()
+    }
+  }
+  pub fn kd_at(&self, uv: (f32, f32), point: &Point) -> f32 {
+    textured(self.kd, &self.kd_texture, uv, point)
+  }
+  pub fn ks_at(&self, uv: (f32, f32), point: &Point) -> f32 {
+    textured(self.ks, &self.ks_texture, uv, point)
+  }
+  pub fn kt_at(&self, uv: (f32, f32), point: &Point) -> f32 {
+    textured(self.kt, &self.kt_texture, uv, point)
+  }
+  // normal usada no sombreamento, depois de aplicar o mapa de normais e o bump
+  pub fn shading_normal(&self, object: &dyn Object, point: &Point, uv: (f32, f32)) -> Vector {
+    let normal = object.normal_at_point(point);
+    if self.normal_map.is_none() && self.bump.is_none() {
+      return normal;
+    }
+    // base tangente ortonormal (Gram-Schmidt)
+    let tangent = object.tangent_at_point(point);
+    let tangent = (&tangent - normal.dot(&tangent) * &normal).normalized();
+    let bitangent = normal.cross(&tangent);
+
+    let mut normal = normal;
+    if let Some(map) = &self.normal_map {
+      let c = map.color_at(uv, point);
+      let (x, y, z) = (2.0*c.r - 1.0, 2.0*c.g - 1.0, 2.0*c.b - 1.0);
+      normal = (x * &tangent + y * &bitangent + z * &normal).normalized();
+    }
+    if let Some(bump) = &self.bump {
+      // derivadas da altura por diferenças finitas, andando
+      // ao mesmo tempo em (u, v) e no espaço ao longo da base
+      let e = 1e-3;
+      let h = bump.value_at(uv, point);
+      let hu = bump.value_at((uv.0 + e, uv.1), &(point + &tangent * e));
+      let hv = bump.value_at((uv.0, uv.1 + e), &(point + &bitangent * e));
+      let du = (hu - h) / e;
+      let dv = (hv - h) / e;
+      normal = (&normal - self.bump_strength * (du * &tangent + dv * &bitangent)).normalized();
+    }
+    normal
+  }
+  // lei de Beer–Lambert: quanto da luz sobrevive depois
+  // de percorrer `distance` dentro do objeto
+  pub fn transmittance(&self, distance: f32) -> Color {
+    if self.density <= 0.0 {
+      return Color::white();
+    }
+    self.absorption.powf(self.density * distance)
+  }
 }
 
 pub trait Object {
   fn ray_intersection_distance(&self, ray: &Ray) -> Option<f32>;
   fn normal_at_point(&self, point: &Point) -> Vector;
+  // coordenadas de textura (u, v) do ponto na superfície
+  fn uv_at_point(&self, point: &Point) -> (f32, f32);
+  // direção em que u cresce, usada para montar a base tangente
+  fn tangent_at_point(&self, point: &Point) -> Vector;
   fn material(&self) -> &Material;
+  // objetos volumétricos não são superfícies e são sombreados à parte
+  fn volume(&self) -> Option<&Volume> {
+    None
+  }
+  // esfera que envolve o objeto, se ele for limitado
+  fn bounding_sphere(&self) -> Option<(Point, f32)> {
+    None
+  }
+  // superfícies abertas (como o plano) não têm interior
+  fn is_closed(&self) -> bool {
+    true
+  }
 }
 pub struct Sphere {
   material: Material,
@@ -74,21 +200,48 @@ impl Object for Sphere {
   fn normal_at_point(&self, point: &Point) -> Vector {
     (point - &self.xyz).normalized()
   }
+  fn uv_at_point(&self, point: &Point) -> (f32, f32) {
+    let n = self.normal_at_point(point);
+    let u = 0.5 + n.z.atan2(n.x) / (2.0 * std::f32::consts::PI);
+    let v = 0.5 - n.y.clamp(-1.0, 1.0).asin() / std::f32::consts::PI;
+    (u, v)
+  }
+  fn tangent_at_point(&self, point: &Point) -> Vector {
+    let n = self.normal_at_point(point);
+    let tangent = Vector::from(-n.z, 0.0, n.x);
+    if tangent.norm() < 1e-6 {
+      // nos polos a direção de u não é definida
+      return n.perpendicular();
+    }
+    tangent.normalized()
+  }
   fn material(&self) -> &Material {
     &self.material
   }
+  fn bounding_sphere(&self) -> Option<(Point, f32)> {
+    Some((self.xyz.clone(), self.r))
+  }
 }
 pub struct Plane {
   material: Material,
   normal: Vector,
-  d: f32
+  d: f32,
+  // base do plano, usada nas coordenadas de textura
+  origin: Point,
+  u_axis: Vector,
+  v_axis: Vector
 }
 impl Plane {
   pub fn from(material: Material, normal: Vector, point: &Point) -> Plane {
+    let u_axis = normal.perpendicular();
+    let v_axis = normal.cross(&u_axis).normalized();
     Plane {
       material,
       d: normal.dot_point(point),
-      normal
+      normal,
+      origin: point.clone(),
+      u_axis,
+      v_axis
     }
   }
 }
@@ -109,15 +262,204 @@ impl Object for Plane {
   fn normal_at_point(&self, _point: &Point) -> Vector {
     self.normal.clone()
   }
+  fn uv_at_point(&self, point: &Point) -> (f32, f32) {
+    let p = point - &self.origin;
+    (p.dot(&self.u_axis), p.dot(&self.v_axis))
+  }
+  fn tangent_at_point(&self, _point: &Point) -> Vector {
+    self.u_axis.clone()
+  }
+  fn material(&self) -> &Material {
+    &self.material
+  }
+  fn is_closed(&self) -> bool {
+    false
+  }
+}
+
+// Volume heterogêneo (fumaça, nuvens) dentro de uma caixa alinhada aos eixos.
+// Não é uma superfície: a "intersecção" é o ponto onde o raio colide com uma
+// partícula, sorteado por delta tracking. O rgb do material é o albedo
+pub struct Volume {
+  material: Material,
+  min: Point,
+  max: Point,
+  density: DensityField,
+  scale: f32, // multiplica a densidade do campo
+  pub g: f32 // anisotropia do espalhamento
+}
+impl Volume {
+  pub fn from(material: Material, min: Point, max: Point, density: DensityField, scale: f32, g: f32) -> Volume {
+    Volume {
+      material, min, max, density, scale, g
+    }
+  }
+  fn density_at(&self, point: &Point) -> f32 {
+    let size = &self.max - &self.min;
+    let p = point - &self.min;
+    let local = Point::from(p.x / size.x, p.y / size.y, p.z / size.z);
+    self.scale * self.density.density(&local)
+  }
+  fn majorant(&self) -> f32 {
+    self.scale * self.density.max()
+  }
+  // trecho [t0, t1] do raio dentro da caixa
+  fn box_interval(&self, ray: &Ray) -> Option<(f32, f32)> {
+    let mut t0 = 0.0f32;
+    let mut t1 = f32::INFINITY;
+    let axes = [
+      (ray.origin.x, ray.direction.x, self.min.x, self.max.x),
+      (ray.origin.y, ray.direction.y, self.min.y, self.max.y),
+      (ray.origin.z, ray.direction.z, self.min.z, self.max.z)
+    ];
+    for (o, d, min, max) in axes.iter() {
+      let ta = (min - o) / d;
+      let tb = (max - o) / d;
+      t0 = t0.max(ta.min(tb));
+      t1 = t1.min(ta.max(tb));
+    }
+    if t0 < t1 { Some((t0, t1)) } else { None }
+  }
+  // fração da luz que atravessa o volume entre t = 0 e t = max_t (ratio tracking)
+  pub fn transmittance(&self, ray: &Ray, max_t: f32) -> f32 {
+    let majorant = self.majorant();
+    let (t0, t1) = match self.box_interval(ray) {
+      Some((t0, t1)) if majorant > 0.0 => (t0, t1.min(max_t)),
+      _ => return 1.0
+    };
+    let mut rng = sampler::rng();
+    let step = majorant * ray.direction.norm();
+    let mut transmittance = 1.0;
+    let mut t = t0;
+    loop {
+      t -= (1.0 - rng.gen::<f32>()).ln() / step;
+      if t >= t1 {
+        return transmittance;
+      }
+      transmittance *= 1.0 - self.density_at(&ray.point_at(t)) / majorant;
+    }
+  }
+}
+impl Object for Volume {
+  fn ray_intersection_distance(&self, ray: &Ray) -> Option<f32> {
+    // delta tracking: anda distâncias exponenciais segundo a densidade
+    // máxima e aceita uma colisão com probabilidade densidade/máxima
+    let majorant = self.majorant();
+    let (t0, t1) = self.box_interval(ray)?;
+    if majorant <= 0.0 {
+      return None;
+    }
+    let mut rng = sampler::rng();
+    let step = majorant * ray.direction.norm();
+    let mut t = t0;
+    loop {
+      t -= (1.0 - rng.gen::<f32>()).ln() / step;
+      if t >= t1 {
+        return None;
+      }
+      if rng.gen::<f32>() * majorant < self.density_at(&ray.point_at(t)) {
+        return Some(t);
+      }
+    }
+  }
+  // volumes não têm normal nem coordenadas de textura
+  fn normal_at_point(&self, _point: &Point) -> Vector {
+    Vector::from(0.0, 1.0, 0.0)
+  }
+  fn uv_at_point(&self, _point: &Point) -> (f32, f32) {
+    (0.0, 0.0)
+  }
+  fn tangent_at_point(&self, _point: &Point) -> Vector {
+    Vector::from(1.0, 0.0, 0.0)
+  }
   fn material(&self) -> &Material {
     &self.material
   }
+  fn volume(&self) -> Option<&Volume> {
+    Some(self)
+  }
 }
 
+pub enum LightKind {
+  Point,
+  // luz vinda do infinito, como o sol. `direction` é para onde a luz viaja
+  Directional { direction: Vector },
+  // luz pontual restrita a um cone. Ângulos em radianos, medidos a partir
+  // do eixo; entre `inner` e `outer` a intensidade cai segundo `falloff`.
+  // O gobo é uma imagem projetada que tinge a luz
+  Spot { direction: Vector, inner: f32, outer: f32, falloff: f32, gobo: Option<ImageTexture> }
+}
 pub struct Light {
-  pub xyz: Point,
+  pub kind: LightKind,
+  pub xyz: Point, // só para luzes pontuais
   pub difuse: Color,
-  pub specular: Color
+  pub specular: Color,
+  pub intensity: f32, // em candelas (1 nas cenas antigas)
+  pub attenuation: Attenuation
+}
+impl Light {
+  // direção (normalizada) de `point` para a luz
+  pub fn direction_from(&self, point: &Point) -> Vector {
+    match &self.kind {
+      LightKind::Directional { direction } => (-direction).normalized(),
+      _ => (&self.xyz - point).normalized()
+    }
+  }
+  pub fn distance_from(&self, point: &Point) -> f32 {
+    match &self.kind {
+      LightKind::Directional { .. } => f32::INFINITY,
+      _ => (&self.xyz - point).norm()
+    }
+  }
+  // fator que multiplica as cores da luz ao iluminar `point`
+  pub fn attenuation_at(&self, point: &Point) -> Color {
+    self.intensity * self.falloff(self.distance_from(point)) * self.cone_at(point)
+  }
+  // queda da luz com a distância, segundo o modelo de atenuação
+  pub fn falloff(&self, distance: f32) -> f32 {
+    match self.attenuation {
+      _ if distance.is_infinite() => 1.0,
+      Attenuation::None => 1.0,
+      Attenuation::InverseSquare => 1.0 / (distance * distance).max(1e-4),
+      Attenuation::Custom { constant, linear, quadratic } => {
+        1.0 / (constant + linear * distance + quadratic * distance * distance).max(1e-4)
+      }
+    }
+  }
+  // recorte e projeção das luzes spot
+  pub fn cone_at(&self, point: &Point) -> Color {
+    match &self.kind {
+      LightKind::Spot { direction, inner, outer, falloff, gobo } => {
+        let axis = direction.normalized();
+        let to_point = point - &self.xyz;
+        let cos_theta = axis.dot(&to_point.normalized());
+        let (cos_inner, cos_outer) = (inner.cos(), outer.cos());
+        if cos_theta <= cos_outer {
+          return Color::black();
+        }
+        let cone = if cos_theta >= cos_inner {
+          1.0
+        } else {
+          ((cos_theta - cos_outer) / (cos_inner - cos_outer)).powf(*falloff)
+        };
+        match gobo {
+          Some(gobo) => {
+            // projeta o ponto num plano perpendicular ao eixo;
+            // a imagem cobre exatamente o cone externo
+            let u_axis = axis.perpendicular();
+            let v_axis = axis.cross(&u_axis);
+            let depth = to_point.dot(&axis);
+            let size = outer.tan();
+            let x = to_point.dot(&u_axis) / depth / size;
+            let y = to_point.dot(&v_axis) / depth / size;
+            gobo.sample((0.5 + 0.5*x, 0.5 - 0.5*y)) * cone
+          },
+          None => Color::from(cone, cone, cone)
+        }
+      },
+      _ => Color::white()
+    }
+  }
 }
 /* never used
 impl Light {
@@ -131,13 +473,82 @@ impl Light {
 }
 */
 
+// interpola linearmente entre as paradas vizinhas de `t`.
+// As paradas devem estar ordenadas pela posição
+fn gradient_color(stops: &[GradientStop], t: f32) -> Color {
+  let (first, last) = match (stops.first(), stops.last()) {
+    (Some(first), Some(last)) => (first, last),
+    _ => return Color::black()
+  };
+  if t <= first.position {
+    return Color::from_array(&first.color);
+  }
+  for pair in stops.windows(2) {
+    let (a, b) = (&pair[0], &pair[1]);
+    if t <= b.position {
+      let span = b.position - a.position;
+      let f = if span > 0.0 { (t - a.position) / span } else { 1.0 };
+      return (1.0 - f) * Color::from_array(&a.color) + f * Color::from_array(&b.color);
+    }
+  }
+  Color::from_array(&last.color)
+}
+
+// direção refratada pela lei de Snell. `normal` aponta para o lado de
+// onde `direction` vem e `eta` é a razão entre os índices (origem/destino)
+pub fn refract(direction: &Vector, normal: &Vector, eta: f32) -> Option<Vector> {
+  let cos_i = -normal.dot(direction);
+  let k = 1.0 - eta*eta*(1.0 - cos_i*cos_i);
+  if k < 0.0 {
+    return None; // reflexão interna total
+  }
+  Some(eta*direction + (eta*cos_i - k.sqrt())*normal)
+}
+// direção com ângulo polar de cosseno `cos_theta` em relação a `axis`
+// (unitário) e azimute `phi` em torno dele
+pub fn direction_around(axis: &Vector, cos_theta: f32, phi: f32) -> Vector {
+  let sin_theta = (1.0 - cos_theta*cos_theta).max(0.0).sqrt();
+  let u_axis = axis.perpendicular();
+  let v_axis = axis.cross(&u_axis);
+  (axis * cos_theta + &u_axis * (sin_theta * phi.cos()) + &v_axis * (sin_theta * phi.sin())).normalized()
+}
+// direção uniforme dentro do cone em torno de `axis` cujo ângulo máximo
+// tem cosseno `cos_max` (-1 cobre a esfera toda), com densidade `cone_pdf`
+pub fn sample_cone(axis: &Vector, cos_max: f32, u1: f32, u2: f32) -> Vector {
+  direction_around(axis, 1.0 - u1 * (1.0 - cos_max), 2.0 * std::f32::consts::PI * u2)
+}
+// densidade (em ângulo sólido) de `sample_cone`
+pub fn cone_pdf(cos_max: f32) -> f32 {
+  1.0 / (2.0 * std::f32::consts::PI * (1.0 - cos_max))
+}
+// quantas vezes um fóton pode refratar antes de ser abandonado
+const MAX_PHOTON_BOUNCES: u32 = 8;
+
+// limite de superfícies que um raio de sombra atravessa em um mesmo objeto
+const MAX_SHADOW_CROSSINGS: u32 = 8;
+// deslocamento após cada travessia, para não colidir de novo no mesmo ponto
+const SHADOW_EPSILON: f32 = 1e-3;
+
+fn random_in_unit_sphere<R: Rng>(rng: &mut R) -> Vector {
+  loop {
+    let v = Vector::from(
+      rng.gen::<f32>()*2.0 - 1.0,
+      rng.gen::<f32>()*2.0 - 1.0,
+      rng.gen::<f32>()*2.0 - 1.0
+    );
+    if v.dot(&v) <= 1.0 {
+      return v;
+    }
+  }
+}
+
 // ---
 // #[derive(Debug)]
-struct Bounds {
-  tl: Point,
-  tr: Point,
-  bl: Point,
-  br: Point
+pub struct Bounds {
+  pub tl: Point,
+  pub tr: Point,
+  pub bl: Point,
+  pub br: Point
 }
 pub struct Camera {
   pub xyz: Point,
@@ -148,7 +559,19 @@ pub struct Camera {
   pub image_plane_distance: f32,
   pub width: i32,
   pub height: i32,
-  pub background: Background
+  pub background: Background,
+  pub environment: Option<Environment>, // imagem do fundo `Environment`
+  pub sky: Option<Sky>, // modelo do fundo `SunSky`
+  pub transparent: bool, // fundo visto diretamente fica transparente
+  pub spectrum: Option<Spectrum>, // modo espectral
+  pub medium: Option<Medium>, // meio participante global (névoa)
+  pub outline: Option<Outline>, // contornos (ilustração)
+  pub ambient_occlusion: Option<AmbientOcclusion>,
+  pub integrator: Integrator,
+  pub caustics: Option<Caustics>, // mapa de fótons de cáusticas
+  pub light_sampler: Option<LightSampler>, // sorteio de luzes, em cenas com muitas
+  pub max_depth: u32, // reflexões e refrações seguidas
+  pub min_weight: f32 // contribuição mínima para seguir um raio
 }
 impl Camera {
   pub fn set_target(&mut self, target: Point) {
@@ -162,10 +585,10 @@ impl Camera {
     self.image_plane_distance = dist;
     self.fov = 2.0 * (1.0 / dist).atan();
   }
-  fn left_orientation(&self) -> Vector {
+  pub fn left_orientation(&self) -> Vector {
     self.orientation.rotate_along(&self.target, -std::f32::consts::PI/2.0)
   }
-  fn image_plane_bounds(&self) -> Bounds {
+  pub fn image_plane_bounds(&self) -> Bounds {
     let ratio = (self.height as f32) / (self.width as f32);
     let w = 2.0; // sempre 2
     let h = w * ratio;
@@ -181,7 +604,7 @@ impl Camera {
       br: &center + (-&vert + &hori)
     }
   }
-  fn ray_for_pixel(&self, x: f32, y: f32, bounds: &Bounds) -> Ray {
+  pub fn ray_for_pixel(&self, x: f32, y: f32, bounds: &Bounds) -> Ray {
     let xt = x / (self.width as f32);
     let yt = y / (self.height as f32);
     
@@ -192,10 +615,11 @@ impl Camera {
 
     Ray {
       direction: (&point - &self.xyz).normalized(),
-      origin: point
+      origin: point,
+      wavelength: None
     }
   }
-  fn bg_color_for_ray(&self, ray: &Ray) -> Color {
+  pub fn bg_color_for_ray(&self, ray: &Ray) -> Color {
     match &self.background {
       Background::Sky => {
         let direction = ray.direction.normalized();
@@ -209,7 +633,20 @@ impl Camera {
           b: 1.0
         }
       },
-      Background::Black => Color::black()
+      Background::Black => Color::black(),
+      Background::Solid(color) => Color::from_array(color),
+      Background::Gradient { stops, axis } => {
+        let alignment = ray.direction.normalized().dot(&Vector::from_array(axis).normalized());
+        gradient_color(stops, 0.5 * (alignment + 1.0))
+      },
+      Background::Environment { .. } => match &self.environment {
+        Some(environment) => environment.radiance(&ray.direction),
+        None => Color::black()
+      },
+      Background::SunSky { .. } => match &self.sky {
+        Some(sky) => sky.radiance(&ray.direction),
+        None => Color::black()
+      }
     }
   }
   fn closest_object_index(&self, ray: &Ray, objects: &Vec<Box<Object>>) -> (i32, f32) {
@@ -229,89 +666,528 @@ impl Camera {
     }
     return (index, sml);
   }
-  fn is_shadowed(&self, ray: &Ray, objects: &Vec<Box<Object>>, ignore_index: usize) -> bool {
-    let mut sml = -1.0;
+  // fração da luz que chega de `max_t` até a origem de `ray`.
+  // Objetos opacos bloqueiam tudo; os transparentes (kt > 0) tingem a luz
+  // com a própria cor ao entrar e a absorvem (Beer-Lambert) por dentro.
+  // Volumes atenuam segundo a própria densidade
+  fn shadow_transmittance(&self, ray: &Ray, objects: &Vec<Box<dyn Object>>, ignore_index: usize, max_t: f32) -> Color {
+    let mut transmittance = Color::white();
     for (i, item) in objects.iter().enumerate() {
       if i == ignore_index {
         continue;
       }
-      if let Some(t) = item.ray_intersection_distance(ray) {
-        if sml == -1.0 || t < sml {
-          sml = t;
+      if let Some(volume) = item.volume() {
+        transmittance *= volume.transmittance(ray, max_t);
+        continue;
+      }
+      let material = item.material();
+      // percorre as superfícies do objeto ao longo do raio, uma por vez
+      let mut segment = Ray {
+        origin: ray.origin.clone(),
+        direction: ray.direction.clone(),
+        wavelength: ray.wavelength
+      };
+      let mut traveled = 0.0;
+      let mut entered = None;
+      // com o mapa de fótons, a luz que atravessa os objetos que recebem
+      // fótons (os limitados, ver `trace_caustics`) chega só pelas cáusticas
+      let photon_target = self.caustics.is_some() && item.bounding_sphere().is_some();
+      for _ in 0..MAX_SHADOW_CROSSINGS {
+        let t = match item.ray_intersection_distance(&segment) {
+          Some(t) if t > 0.0 && traveled + t <= max_t => t,
+          _ => break
+        };
+        let point = segment.point_at(t);
+        let uv = item.uv_at_point(&point);
+        let kt = material.kt_at(uv, &point);
+        if kt <= 0.0 || photon_target {
+          return Color::black();
+        }
+        traveled += t;
+        if !item.is_closed() {
+          // sem interior, o raio só atravessa a superfície: tinge pelos dois lados
+          transmittance *= material.color_at(uv, &point) * kt;
+        }
+        else if item.normal_at_point(&point).dot(&ray.direction) < 0.0 {
+          transmittance *= material.color_at(uv, &point) * kt;
+          entered = Some(traveled);
+        }
+        else {
+          transmittance *= material.transmittance(traveled - entered.unwrap_or(0.0));
+          entered = None;
+        }
+        segment.origin = &point + &ray.direction * SHADOW_EPSILON;
+        traveled += SHADOW_EPSILON;
+      }
+      // a luz está dentro do objeto
+      if let Some(start) = entered {
+        if max_t.is_finite() {
+          transmittance *= material.transmittance(max_t - start);
         }
       }
     }
-    return sml > 0.0 && sml <= 1.0;
+    transmittance
   }
-  fn color_for_ray(&self, ray: &Ray, objects: &Vec<Box<Object>>, lights: &Vec<Light>, depth: u32, kr: f32) -> Color {
-    let (closest_index, t) = self.closest_object_index(ray, objects);
+  // Soma `contribution` de todas as luzes. Com o sorteio de luzes ligado,
+  // só as de `always` e algumas sorteadas são calculadas, cada sorteada
+  // dividida pela probabilidade de ter sido escolhida
+  fn sum_lights<F: Fn(&Light) -> Color>(&self, lights: &[Light], contribution: F) -> Color {
+    let mut color = Color::black();
+    match &self.light_sampler {
+      Some(sampler) => {
+        for &i in sampler.always.iter() {
+          color += contribution(&lights[i]);
+        }
+        if !sampler.is_empty() {
+          let mut rng = sampler::rng();
+          for _ in 0..sampler.samples {
+            let (i, probability) = sampler.sample(rng.gen());
+            color += contribution(&lights[i]) / (probability * sampler.samples as f32);
+          }
+        }
+      },
+      None => {
+        for light in lights.iter() {
+          color += contribution(light);
+        }
+      }
+    }
+    color
+  }
+  // raio de sombra de `point` até a luz, com a distância até ela
+  fn shadow_ray(&self, point: &Point, light: &Light) -> (Ray, f32) {
+    let ray = Ray {
+      origin: point.clone(),
+      direction: light.direction_from(point),
+      wavelength: None
+    };
+    (ray, light.distance_from(point))
+  }
+  // Atenua `color`, que chega ao fim de um trecho de `distance` ao longo de
+  // `ray`, e soma a luz espalhada pelo meio em direção ao observador
+  // (espalhamento simples, integrado em passos com deslocamento aleatório)
+  fn through_medium(&self, medium: &Medium, ray: &Ray, distance: f32, color: Color, objects: &Vec<Box<dyn Object>>, lights: &[Light]) -> Color {
+    let direction = ray.direction.normalized();
+    let steps = medium.steps.max(1);
+    let step = distance / (steps as f32);
+    let offset: f32 = sampler::rng().gen();
+    let mut scattered = Color::black();
+    for i in 0..steps {
+      let s = (i as f32 + offset) * step;
+      let point = &ray.origin + &direction * s;
+      let incoming = self.sum_lights(lights, |light| {
+        let (shadow_ray, light_distance) = self.shadow_ray(&point, light);
+        let shadow = self.shadow_transmittance(&shadow_ray, objects, objects.len(), light_distance);
+        if shadow.is_black() {
+          return Color::black();
+        }
+        let phase = medium.phase(&direction, &shadow_ray.direction);
+        // luzes direcionais atravessam o meio só até max_distance
+        let in_medium = light_distance.min(medium.max_distance);
+        &light.difuse * light.attenuation_at(&point) * shadow * medium.transmittance(in_medium) * phase
+      });
+      scattered += medium.transmittance(s) * incoming;
+    }
+    color * medium.transmittance(distance) + &medium.scattering * scattered * step
+  }
+  // luz espalhada por um volume no ponto onde o raio colidiu com ele
+  fn volume_color(&self, volume: &Volume, index: usize, ray: &Ray, t: f32, objects: &Vec<Box<dyn Object>>, lights: &[Light]) -> Color {
+    let point = ray.point_at(t);
+    let direction = ray.direction.normalized();
+    let albedo = &volume.material().rgb;
+    let ambient = albedo * &self.ambient_light;
+    ambient + self.sum_lights(lights, |light| {
+      let (shadow_ray, light_distance) = self.shadow_ray(&point, light);
+      // o próprio volume atenua a luz (auto-sombreamento),
+      // os demais objetos são testados como de costume
+      let shadow = self.shadow_transmittance(&shadow_ray, objects, index, light_distance);
+      if shadow.is_black() {
+        return Color::black();
+      }
+      let transmittance = volume.transmittance(&shadow_ray, light_distance);
+      let phase = henyey_greenstein(volume.g, &direction, &shadow_ray.direction);
+      albedo * &light.difuse * light.attenuation_at(&point) * shadow * (phase * transmittance)
+    })
+  }
+  // média de vários raios perturbados em torno de `ray`, que sai da
+  // superfície na direção de reflexão ou refração perfeita. Os raios
+  // perturbados ficam do mesmo lado da superfície que `ray`.
+  // Só a primeira superfície fosca do caminho se divide em vários raios
+  // (`split`); daí em diante cada uma usa um só, senão o custo explode
+  #[allow(clippy::too_many_arguments)]
+  fn glossy_color(&self, ray: &Ray, normal: &Vector, material: &Material, objects: &Vec<Box<dyn Object>>, lights: &Vec<Light>, depth: u32, weight: f32, kr: f32, split: bool) -> Color {
+    let origin = &ray.origin;
+    if material.roughness <= 0.0 || material.glossy_samples == 0 {
+      let ray = Ray {
+        origin: origin + &ray.direction*0.001,
+        direction: ray.direction.clone(),
+        wavelength: ray.wavelength
+      };
+      return self.color_for_ray(&ray, objects, lights, depth, weight, kr, split);
+    }
+    let samples = if split { material.glossy_samples } else { 1 };
+    let side = ray.direction.dot(normal) > 0.0;
+    let direction = ray.direction.normalized();
+    let mut rng = sampler::rng();
+    let mut color = Color::black();
+    for _ in 0..samples {
+      let mut perturbed = direction.clone();
+      for _ in 0..8 {
+        let candidate = (&direction + material.roughness * random_in_unit_sphere(&mut rng)).normalized();
+        if (candidate.dot(normal) > 0.0) == side {
+          perturbed = candidate;
+          break;
+        }
+      }
+      let ray = Ray {
+        origin: origin + &perturbed*0.001,
+        direction: perturbed,
+        wavelength: ray.wavelength
+      };
+      color += self.color_for_ray(&ray, objects, lights, depth, weight, kr, false);
+    }
+    color / (samples as f32)
+  }
+  // Dispara `count` fótons das luzes em direção aos objetos transparentes
+  // e guarda onde eles chegam a uma superfície difusa depois de refratar.
+  // A potência de cada luz segue a mesma escala das cores: uma luz de cor C
+  // tem intensidade C em todas as direções
+  pub fn trace_caustics(&self, objects: &Vec<Box<dyn Object>>, lights: &[Light], count: u32) -> Vec<Photon> {
+    let targets: Vec<(Point, f32)> = objects.iter()
+      .filter(|item| item.volume().is_none() && item.material().kt > 0.0)
+      .filter_map(|item| item.bounding_sphere())
+      .collect();
+    let mut photons = Vec::new();
+    if targets.is_empty() || lights.is_empty() {
+      return photons;
+    }
+    let per_target = (count as usize / (targets.len() * lights.len())).max(1);
+    let mut rng = sampler::rng();
+    // cada fóton é uma amostra à parte, numa "linha" fora da imagem
+    let mut emitted = 0;
+    for light in lights.iter() {
+      for (center, r) in targets.iter() {
+        for _ in 0..per_target {
+          sampler::start(emitted, u32::MAX, 0, 1);
+          emitted += 1;
+          let (u1, u2): (f32, f32) = (rng.gen(), rng.gen());
+          // origem, direção e potência do fóton, amostrando só a
+          // região que enxerga o alvo
+          let (origin, direction, power) = match &light.kind {
+            LightKind::Directional { direction } => {
+              let axis = direction.normalized();
+              let u_axis = axis.perpendicular();
+              let v_axis = axis.cross(&u_axis);
+              let (radius, angle) = (r * u1.sqrt(), 2.0 * std::f32::consts::PI * u2);
+              let origin = center - &axis * (2.0 * r) + &u_axis * (radius * angle.cos()) + &v_axis * (radius * angle.sin());
+              let area = std::f32::consts::PI * r * r;
+              (origin, axis, &light.difuse * (light.intensity * area))
+            },
+            _ => {
+              let to_center = center - &light.xyz;
+              let distance = to_center.norm();
+              let (axis, cos_max) = if distance > *r {
+                (to_center.normalized(), (1.0 - r*r/(distance*distance)).sqrt())
+              } else {
+                (Vector::from(0.0, 1.0, 0.0), -1.0)
+              };
+              let direction = sample_cone(&axis, cos_max, u1, u2);
+              let solid_angle = 1.0 / cone_pdf(cos_max);
+              let cone = light.cone_at(&(&light.xyz + &direction));
+              (light.xyz.clone(), direction, &light.difuse * cone * (light.intensity * solid_angle))
+            }
+          };
+          let ray = Ray { origin, direction, wavelength: None };
+          self.trace_photon(light, ray, power / (per_target as f32), objects, &mut photons);
+        }
+      }
+    }
+    photons
+  }
+  fn trace_photon(&self, light: &Light, mut ray: Ray, mut power: Color, objects: &Vec<Box<dyn Object>>, photons: &mut Vec<Photon>) {
+    let mut refracted = false;
+    for _ in 0..MAX_PHOTON_BOUNCES {
+      let (index, t) = self.closest_object_index(&ray, objects);
+      if index == -1 {
+        return;
+      }
+      let item = &objects[index as usize];
+      if item.volume().is_some() {
+        return;
+      }
+      let material = item.material();
+      let point = ray.point_at(t);
+      let uv = item.uv_at_point(&point);
+      let kt = material.kt_at(uv, &point);
+      if kt <= 0.0 {
+        // só os caminhos que passaram por um objeto transparente são
+        // cáusticas; a luz direta já vem dos raios de sombra
+        if refracted && material.kd_at(uv, &point) > 0.0 {
+          // a densidade dos fótons já cai com o quadrado da distância;
+          // troca essa queda pela do modelo de atenuação da luz, como na
+          // iluminação direta
+          let distance = light.distance_from(&point);
+          if distance.is_finite() {
+            power *= light.falloff(distance) * distance * distance;
+          }
+          photons.push(Photon { position: point, direction: ray.direction, power });
+        }
+        return;
+      }
+      let normal = item.normal_at_point(&point);
+      let ior = material.ior(None);
+      let entering = normal.dot(&ray.direction) < 0.0;
+      let (normal, eta) = if entering {
+        power *= material.color_at(uv, &point) * kt;
+        (normal, 1.0 / ior)
+      } else {
+        power *= material.transmittance(t);
+        (-normal, ior)
+      };
+      let direction = refract(&ray.direction, &normal, eta).unwrap_or_else(|| {
+        &ray.direction - 2.0 * ray.direction.dot(&normal) * &normal
+      });
+      refracted = true;
+      ray = Ray {
+        origin: &point + &direction * SHADOW_EPSILON,
+        direction,
+        wavelength: None
+      };
+    }
+  }
+  // fração de raios, distribuídos pelo cosseno no hemisfério de `normal`,
+  // que escapam sem bater em nada a menos de `max_distance`
+  fn visibility(&self, settings: &AmbientOcclusion, point: &Point, normal: &Vector, objects: &Vec<Box<dyn Object>>, ignore_index: usize) -> f32 {
+    if settings.samples == 0 {
+      return 1.0;
+    }
+    let mut rng = sampler::rng();
+    let mut visible = 0;
+    for _ in 0..settings.samples {
+      let direction = (normal + random_in_unit_sphere(&mut rng).normalized()).normalized();
+      let ray = Ray {
+        origin: point.clone(),
+        direction,
+        wavelength: None
+      };
+      let occluded = objects.iter().enumerate().any(|(i, item)| {
+        i != ignore_index && item.volume().is_none() && match item.ray_intersection_distance(&ray) {
+          Some(t) => t > 0.0 && t < settings.max_distance,
+          None => false
+        }
+      });
+      if !occluded {
+        visible += 1;
+      }
+    }
+    visible as f32 / settings.samples as f32
+  }
+  // modo de oclusão ambiente: branco onde nada oculta o céu
+  fn occlusion_color(&self, ray: &Ray, (closest_index, t): (i32, f32), objects: &Vec<Box<dyn Object>>) -> Color {
     if closest_index == -1 {
-      return self.bg_color_for_ray(ray);
+      return Color::white();
+    }
+    let item = &objects[closest_index as usize];
+    let point = ray.point_at(t);
+    let mut normal = item.normal_at_point(&point);
+    if normal.dot(&ray.direction) > 0.0 {
+      normal = -normal; // visto por trás (planos)
+    }
+    let settings = self.ambient_occlusion.unwrap_or_default();
+    let visibility = self.visibility(&settings, &point, &normal, objects, closest_index as usize);
+    Color::from(visibility, visibility, visibility)
+  }
+  // luz refletida para o observador por uma luz de cores `difuse` e
+  // `specular` vinda de `light_direction`, segundo o modelo do material
+  #[allow(clippy::too_many_arguments)]
+  fn shade(&self, material: &Material, rgb: &Color, kd: f32, ks: f32, normal: &Vector, eye_direction: &Vector, view_direction: &Vector, light_direction: &Vector, (light_difuse, light_specular): (&Color, &Color)) -> Color {
+    let light_normal = normal.dot(light_direction);
+    let (difuse, specular) = match material.model {
+      ShadingModel::Phong => {
+        let difuse = kd * light_difuse * light_normal;
+        // especular
+        let reflectance = 2.0 * light_normal * normal - light_direction;
+        let specular = ks * light_specular;
+        let specular = specular * eye_direction.dot(&reflectance).powf(material.alpha);
+        (difuse, specular)
+      },
+      ShadingModel::Ggx => {
+        let (difuse, specular) = brdf::cook_torrance(
+          rgb, material.metallic, material.roughness,
+          normal, view_direction, light_direction
+        );
+        (kd * difuse * light_difuse, ks * specular * light_specular)
+      },
+      ShadingModel::Toon => {
+        // termo difuso quantizado em faixas e brilho especular "chapado"
+        let bands = material.bands.max(1) as f32;
+        let level = (light_normal * bands).ceil() / bands;
+        let difuse = kd * rgb * light_difuse * level;
+        let reflectance = 2.0 * light_normal * normal - light_direction;
+        let highlight = eye_direction.dot(&reflectance).max(0.0).powf(material.alpha);
+        let specular = if highlight > 0.5 { ks * light_specular } else { Color::black() };
+        (difuse, specular)
+      }
+    };
+    difuse + specular
+  }
+  // `depth` é quantas reflexões ou refrações ainda podem seguir este raio
+  // e `weight` é quanto a cor dele contribui para o pixel
+  #[allow(clippy::too_many_arguments)]
+  fn color_for_ray(&self, ray: &Ray, objects: &Vec<Box<Object>>, lights: &Vec<Light>, depth: u32, weight: f32, kr: f32, split: bool) -> Color {
+    let hit = self.closest_object_index(ray, objects);
+    self.color_for_hit(ray, hit, objects, lights, depth, weight, kr, split)
+  }
+  // cor de `ray`, que já se sabe atingir `closest_index` em `t`
+  #[allow(clippy::too_many_arguments)]
+  fn color_for_hit(&self, ray: &Ray, (closest_index, t): (i32, f32), objects: &Vec<Box<Object>>, lights: &Vec<Light>, depth: u32, weight: f32, kr: f32, split: bool) -> Color {
+    // fora de objetos transmissivos, o raio atravessa o meio global
+    let global_medium = if kr == 1.0 { self.medium.as_ref() } else { None };
+    if closest_index == -1 {
+      let color = self.bg_color_for_ray(ray);
+      return match global_medium {
+        Some(medium) => self.through_medium(medium, ray, medium.max_distance, color, objects, lights).clip(),
+        None => color
+      };
     }
     let item = &objects[closest_index as usize];
     let material = item.material();
+    if let Some(volume) = item.volume() {
+      let color = self.volume_color(volume, closest_index as usize, ray, t, objects, lights);
+      let color = match global_medium {
+        Some(medium) => self.through_medium(medium, ray, t * ray.direction.norm(), color, objects, lights),
+        None => color
+      };
+      return color.clip();
+    }
     let intersection = ray.point_at(t);
-    let normal = item.normal_at_point(&intersection);
+    let uv = item.uv_at_point(&intersection);
+    let normal = material.shading_normal(item.as_ref(), &intersection, uv);
     let eye_direction = (&self.xyz - &intersection).normalized();
+    let view_direction = (-&ray.direction).normalized();
+    let rgb = material.color_at(uv, &intersection);
+    let kd = material.kd_at(uv, &intersection);
+    let ks = material.ks_at(uv, &intersection);
+    let kt = material.kt_at(uv, &intersection);
+    // se kr != 1, o raio está viajando por dentro de um objeto transmissivo
+    // e é atenuado pela distância percorrida até a saída
+    let transmittance = if kr != 1.0 {
+      material.transmittance(t * ray.direction.norm())
+    } else {
+      Color::white()
+    };
+    let weight = weight * transmittance.max_component();
 
     let mut color = Color {r: 0.0, g: 0.0, b: 0.0};
 
     // refração
     let mut refrated = false;
-    if material.kt > 0.0 {
-      let rfactor = kr / material.kr;
-      let c1 = normal.dot(&ray.direction.normalized());
-      let tmp = 1.0 - (rfactor*rfactor)*(1.0 - c1*c1);
-      if tmp > 0.0 {
+    if kt > 0.0 && depth > 0 && weight >= self.min_weight {
+      let ior = material.ior(ray.wavelength);
+      // entrando, a razão é 1/ior; saindo (kr != 1), é ior, com a
+      // normal virada para dentro, como em `trace_photon`
+      let eta = if kr != 1.0 { ior } else { 1.0 / ior };
+      let direction = ray.direction.normalized();
+      let facing = if normal.dot(&direction) < 0.0 { normal.clone() } else { -&normal };
+      if let Some(transmission) = refract(&direction, &facing, eta) {
         // refrata!
-        let c2 = tmp.sqrt();
-        let transmission = rfactor*&ray.direction + (rfactor*c1 - c2)*&normal;
+        let new_kr = if kr != 1.0 { 1.0 } else { ior };
         let new_ray = Ray {
-          origin: &intersection + &transmission*0.001,
-          direction: transmission
+          origin: intersection.clone(),
+          direction: transmission,
+          wavelength: ray.wavelength
         };
-        let new_kr = if kr != 1.0 { 1.0 } else { material.kr };
         refrated = true;
-        color = self.color_for_ray(&new_ray, objects, lights, depth, new_kr);
+        color = self.glossy_color(&new_ray, &normal, material, objects, lights, depth-1, weight, new_kr, split);
       }
     }
     if refrated == false {
-      color = &material.rgb * &self.ambient_light;
+      color = &rgb * &self.ambient_light;
+      if let Some(settings) = &self.ambient_occlusion {
+        color *= self.visibility(settings, &intersection, &normal, objects, closest_index as usize);
+      }
+      if let Some(caustics) = &self.caustics {
+        if kt <= 0.0 {
+          let irradiance = caustics.map.irradiance(&intersection, &normal, caustics.gather, caustics.radius);
+          color += kd * &rgb * irradiance;
+        }
+      }
     }
 
+    // meio entre a origem do raio e a superfície: o do próprio
+    // objeto, se o raio estiver dentro dele, ou o global
+    let medium = if kr != 1.0 { material.medium.as_ref() } else { global_medium };
+
     // iluminação
-    for light in lights.iter() {
-      let light_direction = (&light.xyz - &intersection).normalized();
-      if self.is_shadowed(
-        &Ray{origin: intersection.clone(), direction: light_direction.clone()},
-        objects,
-        closest_index as usize
-      ) {
-        continue;
+    let direct = |light: &Light| {
+      let (shadow_ray, light_distance) = self.shadow_ray(&intersection, light);
+      let shadow = self.shadow_transmittance(&shadow_ray, objects, closest_index as usize, light_distance);
+      if shadow.is_black() {
+        return Color::black();
+      }
+      let mut attenuation = light.attenuation_at(&intersection) * shadow;
+      // a luz também atravessa o meio em que o ponto está
+      if let Some(medium) = medium {
+        attenuation *= medium.transmittance(light_distance.min(medium.max_distance));
       }
+      let light_direction = shadow_ray.direction;
       let light_normal = normal.dot(&light_direction);
       if light_normal < 0.0 {
-        continue; // está vindo por dentro do objeto
+        return Color::black(); // está vindo por dentro do objeto
+      }
+      self.shade(material, &rgb, kd, ks, &normal, &eye_direction, &view_direction, &light_direction, (&light.difuse, &light.specular)) * attenuation
+    };
+    color += self.sum_lights(lights, direct);
+    // o mapa de ambiente como fonte de luz, amostrado por importância
+    if let Some(environment) = &self.environment {
+      let mut rng = sampler::rng();
+      for _ in 0..environment.samples {
+        let (light_direction, radiance, pdf) = environment.sample(rng.gen(), rng.gen());
+        if normal.dot(&light_direction) <= 0.0 || pdf <= 0.0 {
+          continue;
+        }
+        let shadow_ray = Ray {
+          origin: intersection.clone(),
+          direction: light_direction.clone(),
+          wavelength: ray.wavelength
+        };
+        let shadow = self.shadow_transmittance(&shadow_ray, objects, closest_index as usize, f32::INFINITY);
+        if shadow.is_black() {
+          continue;
+        }
+        // uma luz de cor L vale kd * L * N·L numa superfície branca, enquanto
+        // a radiância vinda do ambiente contribui com kd * radiância * N·L / π.
+        // O especular fica só com o raio refletido, que já enxerga o ambiente
+        let light = radiance * shadow / (std::f32::consts::PI * pdf * environment.samples as f32);
+        color += self.shade(material, &rgb, kd, ks, &normal, &eye_direction, &view_direction, &light_direction, (&light, &Color::black()));
       }
-      let difuse = material.kd * &light.difuse * light_normal;
-      // especular
-      let reflectance = 2.0 * light_normal * &normal - &light_direction;
-      let specular = material.ks * &light.specular;
-      let specular = specular * eye_direction.dot(&reflectance).powf(material.alpha);
-      color = color + difuse + specular;
     }
     
     // reflexão
-    if refrated == false && depth > 0 {
+    let reflectance = match material.model {
+      ShadingModel::Phong | ShadingModel::Toon => Color::from(ks, ks, ks),
+      ShadingModel::Ggx => {
+        let f0 = brdf::base_reflectance(&rgb, material.metallic);
+        ks * brdf::fresnel(&f0, normal.dot(&view_direction))
+      }
+    };
+    let reflected_weight = weight * reflectance.max_component();
+    if refrated == false && depth > 0 && reflected_weight >= self.min_weight {
       let rd = (-&ray.direction).normalized();
-      let reflectance = 2.0 * normal.dot(&rd) * &normal - rd;
+      let reflection_direction = 2.0 * normal.dot(&rd) * &normal - rd;
       let reflection_ray = Ray {
-        origin: intersection + &reflectance*0.001,
-        direction: reflectance
+        origin: intersection,
+        direction: reflection_direction,
+        wavelength: ray.wavelength
       };
-      let reflected = self.color_for_ray(&reflection_ray, objects, lights, depth-1, 1.0);
-      color = color + reflected*material.ks;
+      let reflected = self.glossy_color(&reflection_ray, &normal, material, objects, lights, depth-1, reflected_weight, 1.0, split);
+      color += reflected*reflectance;
     }
+    let color = color * transmittance;
+    let color = match medium {
+      Some(medium) => self.through_medium(medium, ray, t * ray.direction.norm(), color, objects, lights),
+      None => color
+    };
     return color.clip();
   }
   /* never used
@@ -363,55 +1239,199 @@ impl Camera {
     return colors2;
   }
   */
-  pub fn take_picture(&self, objects: &Vec<Box<Object>>, lights: &Vec<Light>) -> Vec<Vec<[u8; 3]>> {
+  // cor de um raio primário. No modo espectral, o raio é repetido para
+  // vários comprimentos de onda e as amostras são convertidas de volta para RGB
+  // `hit` é a primeira interseção do raio, a mesma para todos os comprimentos de onda
+  fn primary_color(&self, ray: Ray, hit: (i32, f32), objects: &Vec<Box<dyn Object>>, lights: &Vec<Light>) -> Color {
+    if let Integrator::AmbientOcclusion = self.integrator {
+      return self.occlusion_color(&ray, hit, objects);
+    }
+    let spectrum = match &self.spectrum {
+      Some(spectrum) => spectrum,
+      None => return self.color_for_hit(&ray, hit, objects, lights, self.max_depth, 1.0, 1.0, true)
+    };
+    let mut color = Color::black();
+    for i in 0..spectrum.samples {
+      let wavelength = Spectrum::wavelength(i, spectrum.samples);
+      let ray = Ray {
+        origin: ray.origin.clone(),
+        direction: ray.direction.clone(),
+        wavelength: Some(wavelength)
+      };
+      color += self.color_for_hit(&ray, hit, objects, lights, self.max_depth, 1.0, 1.0, true) * spectrum.weight(wavelength);
+    }
+    (color / (spectrum.samples as f32)).clip()
+  }
+  // cor e cobertura (alfa) de um raio primário. No modo transparente,
+  // o fundo visto diretamente não entra na cor. A cobertura vem da mesma
+  // interseção usada na cor, que nos volumes é aleatória
+  fn primary_sample(&self, ray: Ray, objects: &Vec<Box<dyn Object>>, lights: &Vec<Light>) -> (Color, f32) {
+    let hit = self.closest_object_index(&ray, objects);
+    if self.transparent && hit.0 == -1 {
+      return (Color::black(), 0.0);
+    }
+    (self.primary_color(ray, hit, objects, lights), 1.0)
+  }
+  // Desenha contornos onde pixels vizinhos mudam de objeto ou têm
+  // profundidade ou normal muito diferentes. Usa o raio central de cada
+  // pixel, então o contorno fica nítido mesmo com depth of field
+  pub fn draw_outlines(&self, outline: &Outline, colors: &mut [Vec<[u8; 4]>], objects: &Vec<Box<dyn Object>>) {
+    let bounds = self.image_plane_bounds();
+    let w = self.width as usize;
+    let h = self.height as usize;
+    // (objeto, profundidade, normal) do que cada pixel vê
+    let mut buffer = Vec::with_capacity(w * h);
+    for x in 0..w {
+      for y in 0..h {
+        let ray = self.ray_for_pixel(x as f32, y as f32, &bounds);
+        let (index, t) = self.closest_object_index(&ray, objects);
+        if index == -1 {
+          buffer.push((index, f32::INFINITY, Vector::from(0.0, 0.0, 0.0)));
+        } else {
+          let normal = objects[index as usize].normal_at_point(&ray.point_at(t));
+          buffer.push((index, t * ray.direction.norm(), normal));
+        }
+      }
+    }
+    let differs = |a: usize, b: usize| {
+      let (id_a, depth_a, normal_a) = &buffer[a];
+      let (id_b, depth_b, normal_b) = &buffer[b];
+      if id_a != id_b {
+        return true;
+      }
+      if *id_a == -1 {
+        return false;
+      }
+      (depth_a - depth_b).abs() > outline.depth_threshold * depth_a.min(*depth_b)
+        || normal_a.dot(normal_b) < outline.normal_threshold
+    };
+    let color = Color::from_array(&outline.color).to_rgba(1.0);
+    for (x, column) in colors.iter_mut().enumerate() {
+      for (y, pixel) in column.iter_mut().enumerate() {
+        let i = x * h + y;
+        if (x + 1 < w && differs(i, i + h)) || (y + 1 < h && differs(i, i + 1)) {
+          *pixel = color;
+        }
+      }
+    }
+  }
+  pub fn take_picture(&self, objects: &Vec<Box<Object>>, lights: &Vec<Light>) -> Vec<Vec<[u8; 4]>> {
     let bounds = self.image_plane_bounds();
     let w = self.width as usize;
     let h = self.height as usize;
-    let mut colors = vec![vec![[0u8; 3]; h]; w];
+    let mut colors = vec![vec![[0u8; 4]; h]; w];
     let mut ray:Ray;
     for x in 0..w {
       for y in 0..h {
+        sampler::start(x as u32, y as u32, 0, 1);
         ray = self.ray_for_pixel(x as f32, y as f32, &bounds);
-        colors[x][y] = self.color_for_ray(&ray, objects, lights, 5, 1.0).as_array();
+        let (color, alpha) = self.primary_sample(ray, objects, lights);
+        colors[x][y] = color.to_rgba(alpha);
       }
     }
+    if let Some(outline) = &self.outline {
+      self.draw_outlines(outline, &mut colors, objects);
+    }
 
     return colors;
   }
-  pub fn take_dof_picture(&self, objects: &Vec<Box<Object>>, lights: &Vec<Light>, r: f32, focal_length: f32, samples: u32) -> Vec<Vec<[u8; 3]>> {
+  pub fn take_dof_picture(&self, objects: &Vec<Box<Object>>, lights: &Vec<Light>, r: f32, focal_length: f32, samples: u32) -> Vec<Vec<[u8; 4]>> {
     let bounds = self.image_plane_bounds();
     let w = self.width as usize;
     let h = self.height as usize;
-    let mut colors = vec![vec![[0u8; 3]; h]; w];
+    let mut colors = vec![vec![[0u8; 4]; h]; w];
     let mut ray:Ray;
-    let mut rng = rand::thread_rng();
-    let up = &self.orientation;
-    let left = self.left_orientation();
 
     for x in 0..w {
       for y in 0..h {
         ray = self.ray_for_pixel(x as f32, y as f32, &bounds);
         let mut color = Color::black();
-        let focal_point = ray.point_at(focal_length);
-        for _ in 0..samples {
-          let rx:f32 = rng.gen();
-          let ry:f32 = rng.gen();
-          let dx = (rx*2.0*r - r) * &left;
-          let dy = (ry*2.0*r - r) * up;
-          let new_origin = &ray.origin + (dx + dy);
-          let new_direction = &focal_point - &new_origin;
-          let new_ray = Ray {
-            origin: new_origin,
-            direction: new_direction.normalized()
-          };
-          color += self.color_for_ray(&new_ray, objects, lights, 5, 1.0);  
+        let mut alpha = 0.0;
+        for i in 0..samples {
+          sampler::start(x as u32, y as u32, i, samples);
+          let new_ray = self.lens_ray(&ray, r, focal_length);
+          let (sample, coverage) = self.primary_sample(new_ray, objects, lights);
+          color += sample;
+          alpha += coverage;
         }
-        color = color/(samples as f32);
-        colors[x][y] = color.as_array();
+        color = color.unpremultiply(alpha);
+        alpha /= samples as f32;
+        colors[x][y] = color.to_rgba(alpha);
       }
     }
+    if let Some(outline) = &self.outline {
+      self.draw_outlines(outline, &mut colors, objects);
+    }
     return colors;
   }
+  // raio saindo de um ponto aleatório da lente (um quadrado de lado 2r)
+  // que passa pelo mesmo ponto de `ray` a `focal_length` de distância
+  fn lens_ray(&self, ray: &Ray, r: f32, focal_length: f32) -> Ray {
+    let focal_point = ray.point_at(focal_length);
+    let (rx, ry) = sampler::next_2d();
+    let dx = (rx*2.0*r - r) * self.left_orientation();
+    let dy = (ry*2.0*r - r) * &self.orientation;
+    let new_origin = &ray.origin + (dx + dy);
+    let new_direction = &focal_point - &new_origin;
+    Ray {
+      origin: new_origin,
+      direction: new_direction.normalized(),
+      wavelength: None
+    }
+  }
+  // Amostragem adaptativa: cada pixel recebe raios em posições aleatórias
+  // (e pela lente, se `lens` = (r, distância focal)) até que o erro padrão
+  // da média, tanto do brilho quanto da cobertura, fique abaixo do limite.
+  // Retorna também quantas amostras cada pixel usou
+  pub fn take_adaptive_picture(&self, objects: &Vec<Box<dyn Object>>, lights: &Vec<Light>, settings: &Adaptive, lens: Option<(f32, f32)>) -> (Vec<Vec<[u8; 4]>>, Vec<Vec<u32>>) {
+    let bounds = self.image_plane_bounds();
+    let w = self.width as usize;
+    let h = self.height as usize;
+    let (min_samples, max_samples) = settings.sample_range();
+    let mut colors = vec![vec![[0u8; 4]; h]; w];
+    let mut counts = vec![vec![0u32; h]; w];
+
+    for x in 0..w {
+      for y in 0..h {
+        let mut color = Color::black();
+        let mut alpha = 0.0;
+        // somas e somas dos quadrados do brilho e da cobertura
+        let (mut sum, mut sum2, mut alpha2) = (0.0, 0.0, 0.0);
+        let mut n = 0;
+        while n < max_samples {
+          sampler::start(x as u32, y as u32, n, max_samples);
+          let (rx, ry) = sampler::next_2d();
+          let ray = self.ray_for_pixel(x as f32 + rx, y as f32 + ry, &bounds);
+          let ray = match lens {
+            Some((r, focal_length)) => self.lens_ray(&ray, r, focal_length),
+            None => ray
+          };
+          let (sample, coverage) = self.primary_sample(ray, objects, lights);
+          let luminance = sample.luminance();
+          sum += luminance;
+          sum2 += luminance * luminance;
+          alpha += coverage;
+          alpha2 += coverage * coverage;
+          color += sample;
+          n += 1;
+          if n >= min_samples {
+            let count = n as f32;
+            let error = |sum: f32, sum2: f32| ((sum2 - sum * sum / count).max(0.0) / (count - 1.0) / count).sqrt();
+            if error(sum, sum2) <= settings.threshold && error(alpha, alpha2) <= settings.threshold {
+              break;
+            }
+          }
+        }
+        color = color.unpremultiply(alpha);
+        colors[x][y] = color.to_rgba(alpha / n as f32);
+        counts[x][y] = n;
+      }
+    }
+    if let Some(outline) = &self.outline {
+      self.draw_outlines(outline, &mut colors, objects);
+    }
+    (colors, counts)
+  }
   /* never used
   pub fn focal_length_for_point(&self, point: &Point) -> f32 {
     let center = &self.xyz + self.image_plane_distance*&self.target;
@@ -0,0 +1,156 @@
+use super::base::{Vector, Point, Color};
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::f32::consts::PI;
+
+pub struct Photon {
+  pub position: Point,
+  pub direction: Vector, // para onde o fóton viajava
+  pub power: Color
+}
+
+// kd-tree balanceada guardada implicitamente num vetor: cada nó é o
+// elemento do meio do seu intervalo, e as metades à esquerda e à direita
+// são as subárvores
+pub struct PhotonMap {
+  photons: Vec<Photon>,
+  axes: Vec<usize> // eixo de divisão de cada nó
+}
+
+// fótons de cáustica e os parâmetros da estimativa de radiância
+pub struct Caustics {
+  pub map: PhotonMap,
+  pub gather: usize, // fótons usados em cada estimativa
+  pub radius: f32 // raio máximo da busca
+}
+
+fn coordinate(point: &Point, axis: usize) -> f32 {
+  match axis {
+    0 => point.x,
+    1 => point.y,
+    _ => point.z
+  }
+}
+
+// candidato na busca dos vizinhos; o heap mantém o mais distante no topo
+struct Candidate {
+  distance: f32, // ao quadrado
+  index: usize
+}
+impl PartialEq for Candidate {
+  fn eq(&self, other: &Candidate) -> bool {
+    self.distance == other.distance
+  }
+}
+impl Eq for Candidate {}
+impl PartialOrd for Candidate {
+  fn partial_cmp(&self, other: &Candidate) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+impl Ord for Candidate {
+  fn cmp(&self, other: &Candidate) -> Ordering {
+    self.distance.partial_cmp(&other.distance).unwrap_or(Ordering::Equal)
+  }
+}
+
+impl PhotonMap {
+  pub fn build(mut photons: Vec<Photon>) -> PhotonMap {
+    let mut axes = vec![0; photons.len()];
+    PhotonMap::balance(&mut photons, &mut axes);
+    PhotonMap { photons, axes }
+  }
+  // divide pelo eixo de maior extensão, com a mediana no meio
+  fn balance(photons: &mut [Photon], axes: &mut [usize]) {
+    if photons.len() <= 1 {
+      return;
+    }
+    let mut min = [f32::INFINITY; 3];
+    let mut max = [f32::NEG_INFINITY; 3];
+    for photon in photons.iter() {
+      for axis in 0..3 {
+        let c = coordinate(&photon.position, axis);
+        min[axis] = min[axis].min(c);
+        max[axis] = max[axis].max(c);
+      }
+    }
+    let axis = (0..3)
+      .max_by(|&a, &b| (max[a] - min[a]).partial_cmp(&(max[b] - min[b])).unwrap_or(Ordering::Equal))
+      .unwrap_or(0);
+    let middle = photons.len() / 2;
+    photons.select_nth_unstable_by(middle, |a, b| {
+      coordinate(&a.position, axis).partial_cmp(&coordinate(&b.position, axis)).unwrap_or(Ordering::Equal)
+    });
+    axes[middle] = axis;
+    let (left, right) = photons.split_at_mut(middle);
+    let (left_axes, right_axes) = axes.split_at_mut(middle);
+    PhotonMap::balance(left, left_axes);
+    PhotonMap::balance(&mut right[1..], &mut right_axes[1..]);
+  }
+  pub fn len(&self) -> usize {
+    self.photons.len()
+  }
+  // os `count` fótons mais próximos de `point` a até `max_distance` dele
+  fn nearest(&self, point: &Point, count: usize, max_distance: f32) -> BinaryHeap<Candidate> {
+    let mut heap = BinaryHeap::with_capacity(count + 1);
+    let mut radius = max_distance * max_distance;
+    self.search(0, self.photons.len(), point, count, &mut radius, &mut heap);
+    heap
+  }
+  fn search(&self, start: usize, end: usize, point: &Point, count: usize, radius: &mut f32, heap: &mut BinaryHeap<Candidate>) {
+    if start >= end {
+      return;
+    }
+    let middle = start + (end - start) / 2;
+    let photon = &self.photons[middle];
+    let axis = self.axes[middle];
+    let delta = coordinate(point, axis) - coordinate(&photon.position, axis);
+    // primeiro o lado onde o ponto está; o outro só se a esfera de busca o alcançar
+    let (near, far) = if delta < 0.0 {
+      ((start, middle), (middle + 1, end))
+    } else {
+      ((middle + 1, end), (start, middle))
+    };
+    self.search(near.0, near.1, point, count, radius, heap);
+    let offset = &photon.position - point;
+    let distance = offset.dot(&offset);
+    if distance < *radius {
+      heap.push(Candidate { distance, index: middle });
+      if heap.len() > count {
+        heap.pop();
+      }
+      if heap.len() == count {
+        if let Some(farthest) = heap.peek() {
+          *radius = farthest.distance;
+        }
+      }
+    }
+    if delta * delta < *radius {
+      self.search(far.0, far.1, point, count, radius, heap);
+    }
+  }
+  // irradiância em `point` pela densidade dos fótons vizinhos que
+  // chegaram pelo lado de `normal`
+  pub fn irradiance(&self, point: &Point, normal: &Vector, count: usize, max_distance: f32) -> Color {
+    let heap = self.nearest(point, count, max_distance);
+    if heap.is_empty() {
+      return Color::black();
+    }
+    let radius = if heap.len() == count {
+      heap.peek().map(|farthest| farthest.distance).unwrap_or(0.0)
+    } else {
+      max_distance * max_distance
+    };
+    if radius <= 0.0 {
+      return Color::black();
+    }
+    let mut flux = Color::black();
+    for candidate in heap.iter() {
+      let photon = &self.photons[candidate.index];
+      if photon.direction.dot(normal) < 0.0 {
+        flux += &photon.power;
+      }
+    }
+    flux / (PI * radius)
+  }
+}
@@ -0,0 +1,371 @@
+use super::config::SamplerKind;
+use rand::{RngCore, Error};
+use std::cell::RefCell;
+
+// Gera os números aleatórios de cada amostra de pixel. Cada amostra
+// consome dimensões em sequência (a posição no pixel, a lente, as
+// escolhas de cada reflexão...), e um gerador de baixa discrepância
+// espalha melhor os pontos de cada dimensão entre as amostras do pixel.
+// A semente de cada pixel vem da semente da cena, então a imagem é sempre
+// a mesma, não importa a ordem em que os pixels são calculados
+pub trait Sampler {
+  // prepara a amostra `index` (de `count`) do pixel (x, y)
+  fn start(&mut self, x: u32, y: u32, index: u32, count: u32);
+  // próxima dimensão da amostra, em [0, 1)
+  fn next_1d(&mut self) -> f32;
+  // duas dimensões de uma vez, estratificadas em conjunto quando possível
+  fn next_2d(&mut self) -> (f32, f32) {
+    (self.next_1d(), self.next_1d())
+  }
+}
+
+const ONE_MINUS_EPSILON: f32 = 1.0 - f32::EPSILON / 2.0;
+
+// gerador congruente permutado (PCG32, de O'Neill)
+pub struct Random {
+  state: u64,
+  inc: u64
+}
+impl Random {
+  pub fn new(seed: u64) -> Random {
+    let mut random = Random { state: 0, inc: 1442695040888963407 };
+    random.next_u32();
+    random.state = random.state.wrapping_add(seed);
+    random.next_u32();
+    random
+  }
+  pub fn next_u32(&mut self) -> u32 {
+    let old = self.state;
+    self.state = old.wrapping_mul(6364136223846793005).wrapping_add(self.inc);
+    let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+    xorshifted.rotate_right((old >> 59) as u32)
+  }
+  pub fn next_f32(&mut self) -> f32 {
+    (self.next_u32() >> 8) as f32 / 16777216.0
+  }
+}
+
+// embaralhamento de 64 bits (finalizador do splitmix64)
+fn mix(mut h: u64) -> u64 {
+  h = (h ^ (h >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+  h = (h ^ (h >> 27)).wrapping_mul(0x94d049bb133111eb);
+  h ^ (h >> 31)
+}
+fn hash(values: &[u64]) -> u64 {
+  values.iter().fold(0x9e3779b97f4a7c15, |h, &v| mix(h ^ mix(v)))
+}
+fn to_unit(bits: u32) -> f32 {
+  (bits >> 8) as f32 / 16777216.0
+}
+
+// números independentes: cada amostra tem o próprio gerador
+pub struct Independent {
+  seed: u64,
+  random: Random
+}
+impl Sampler for Independent {
+  fn start(&mut self, x: u32, y: u32, index: u32, _count: u32) {
+    self.random = Random::new(hash(&[self.seed, x as u64, y as u64, index as u64]));
+  }
+  fn next_1d(&mut self) -> f32 {
+    self.random.next_f32()
+  }
+}
+
+// Estratificado: em cada dimensão, as `count` amostras do pixel caem em
+// faixas diferentes de [0, 1) (numa grade, nos pares de `next_2d`),
+// com a ordem das faixas sorteada por dimensão
+pub struct Stratified {
+  seed: u64,
+  pixel: u64,
+  index: u32,
+  count: u32,
+  dimension: u64,
+  random: Random
+}
+impl Sampler for Stratified {
+  fn start(&mut self, x: u32, y: u32, index: u32, count: u32) {
+    self.pixel = hash(&[self.seed, x as u64, y as u64]);
+    self.index = index;
+    self.count = count.max(1);
+    self.dimension = 0;
+    self.random = Random::new(hash(&[self.pixel, index as u64]));
+  }
+  fn next_1d(&mut self) -> f32 {
+    let permutation = hash(&[self.pixel, self.dimension]) as u32;
+    self.dimension += 1;
+    if self.index >= self.count {
+      return self.random.next_f32();
+    }
+    let stratum = permutation_element(self.index, self.count, permutation);
+    ((stratum as f32 + self.random.next_f32()) / self.count as f32).min(ONE_MINUS_EPSILON)
+  }
+  fn next_2d(&mut self) -> (f32, f32) {
+    let permutation = hash(&[self.pixel, self.dimension]) as u32;
+    self.dimension += 2;
+    let side = (self.count as f32).sqrt().ceil() as u32;
+    if self.index >= side * side {
+      return (self.random.next_f32(), self.random.next_f32());
+    }
+    let cell = permutation_element(self.index, side * side, permutation);
+    let x = ((cell % side) as f32 + self.random.next_f32()) / side as f32;
+    let y = ((cell / side) as f32 + self.random.next_f32()) / side as f32;
+    (x.min(ONE_MINUS_EPSILON), y.min(ONE_MINUS_EPSILON))
+  }
+}
+// elemento `i` de uma permutação pseudoaleatória de 0..l escolhida por `p`
+// (Kensler, "Correlated Multi-Jittered Sampling")
+fn permutation_element(mut i: u32, l: u32, p: u32) -> u32 {
+  let mut w = l - 1;
+  w |= w >> 1;
+  w |= w >> 2;
+  w |= w >> 4;
+  w |= w >> 8;
+  w |= w >> 16;
+  loop {
+    i ^= p;
+    i = i.wrapping_mul(0xe170893d);
+    i ^= p >> 16;
+    i ^= (i & w) >> 4;
+    i ^= p >> 8;
+    i = i.wrapping_mul(0x0929eb3f);
+    i ^= p >> 23;
+    i ^= (i & w) >> 1;
+    i = i.wrapping_mul(1 | p >> 27);
+    i = i.wrapping_mul(0x6935fa69);
+    i ^= (i & w) >> 11;
+    i = i.wrapping_mul(0x74dcb303);
+    i ^= (i & w) >> 2;
+    i = i.wrapping_mul(0x9e501cc3);
+    i ^= (i & w) >> 2;
+    i = i.wrapping_mul(0xc860a3df);
+    i &= w;
+    i ^= i >> 5;
+    if i < l {
+      break;
+    }
+  }
+  i.wrapping_add(p) % l
+}
+
+const PRIMES: [u64; 32] = [
+  2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+  59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131
+];
+// Halton: a dimensão d da amostra i é o inverso radical de i na base do
+// d-ésimo primo, deslocado (módulo 1) por um valor sorteado por pixel.
+// Depois dos primos da tabela, as dimensões são independentes
+pub struct Halton {
+  seed: u64,
+  pixel: u64,
+  index: u32,
+  dimension: usize,
+  random: Random
+}
+impl Sampler for Halton {
+  fn start(&mut self, x: u32, y: u32, index: u32, _count: u32) {
+    self.pixel = hash(&[self.seed, x as u64, y as u64]);
+    self.index = index;
+    self.dimension = 0;
+    self.random = Random::new(hash(&[self.pixel, index as u64]));
+  }
+  fn next_1d(&mut self) -> f32 {
+    let dimension = self.dimension;
+    self.dimension += 1;
+    if dimension >= PRIMES.len() {
+      return self.random.next_f32();
+    }
+    let offset = to_unit(hash(&[self.pixel, dimension as u64]) as u32) as f64;
+    let value = (radical_inverse(PRIMES[dimension], self.index as u64) + offset).fract();
+    (value as f32).min(ONE_MINUS_EPSILON)
+  }
+}
+fn radical_inverse(base: u64, mut index: u64) -> f64 {
+  let inverse_base = 1.0 / base as f64;
+  let mut factor = inverse_base;
+  let mut value = 0.0;
+  while index > 0 {
+    value += (index % base) as f64 * factor;
+    index /= base;
+    factor *= inverse_base;
+  }
+  value
+}
+
+// Sobol em pares de dimensões com embaralhamento de Owen, à maneira de
+// Burley ("Practical Hash-based Owen Scrambling"): cada par usa as duas
+// primeiras dimensões de Sobol, com a ordem das amostras e os bits de
+// cada coordenada embaralhados por uma semente própria
+pub struct Sobol {
+  seed: u64,
+  pixel: u64,
+  index: u32,
+  dimension: u64
+}
+impl Sobol {
+  fn seed_for(&mut self, dimensions: u64) -> u32 {
+    let seed = hash(&[self.pixel, self.dimension]) as u32;
+    self.dimension += dimensions;
+    seed
+  }
+}
+impl Sampler for Sobol {
+  fn start(&mut self, x: u32, y: u32, index: u32, _count: u32) {
+    self.pixel = hash(&[self.seed, x as u64, y as u64]);
+    self.index = index;
+    self.dimension = 0;
+  }
+  fn next_1d(&mut self) -> f32 {
+    let seed = self.seed_for(1);
+    let index = nested_uniform_scramble(self.index, seed);
+    to_unit(nested_uniform_scramble(index.reverse_bits(), hash_u32(seed, 0)))
+  }
+  fn next_2d(&mut self) -> (f32, f32) {
+    let seed = self.seed_for(2);
+    let index = nested_uniform_scramble(self.index, seed);
+    (
+      to_unit(nested_uniform_scramble(index.reverse_bits(), hash_u32(seed, 0))),
+      to_unit(nested_uniform_scramble(sobol_second(index), hash_u32(seed, 1)))
+    )
+  }
+}
+// segunda dimensão de Sobol (a primeira é só o índice com os bits invertidos)
+fn sobol_second(mut index: u32) -> u32 {
+  let mut value = 0;
+  let mut direction = 1u32 << 31;
+  while index != 0 {
+    if index & 1 != 0 {
+      value ^= direction;
+    }
+    index >>= 1;
+    direction ^= direction >> 1;
+  }
+  value
+}
+fn hash_u32(seed: u32, value: u64) -> u32 {
+  hash(&[seed as u64, value]) as u32
+}
+// permutação de Laine e Karras: cada bit só depende dos bits menos
+// significativos, o que preserva a estratificação
+fn laine_karras(mut x: u32, seed: u32) -> u32 {
+  x = x.wrapping_add(seed);
+  x ^= x.wrapping_mul(0x6c50b47c);
+  x ^= x.wrapping_mul(0xb82f1e52);
+  x ^= x.wrapping_mul(0xc7afe638);
+  x ^= x.wrapping_mul(0x8d22f6e6);
+  x
+}
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+  laine_karras(x.reverse_bits(), seed).reverse_bits()
+}
+
+pub fn new(kind: SamplerKind, seed: u64) -> Box<dyn Sampler> {
+  let random = Random::new(seed);
+  match kind {
+    SamplerKind::Independent => Box::new(Independent { seed, random }),
+    SamplerKind::Stratified => Box::new(Stratified { seed, pixel: 0, index: 0, count: 1, dimension: 0, random }),
+    SamplerKind::Halton => Box::new(Halton { seed, pixel: 0, index: 0, dimension: 0, random }),
+    SamplerKind::Sobol => Box::new(Sobol { seed, pixel: 0, index: 0, dimension: 0 })
+  }
+}
+
+// O amostrador em uso fica num valor local da thread, para que qualquer
+// parte do renderizador (até a interseção com volumes, que sorteia
+// distâncias) tire dele os seus números sem que ele seja passado adiante
+thread_local! {
+  static CURRENT: RefCell<Box<dyn Sampler>> = RefCell::new(new(SamplerKind::Independent, 0));
+}
+pub fn install(kind: SamplerKind, seed: u64) {
+  CURRENT.with(|current| *current.borrow_mut() = new(kind, seed));
+}
+pub fn start(x: u32, y: u32, index: u32, count: u32) {
+  CURRENT.with(|current| current.borrow_mut().start(x, y, index, count));
+}
+pub fn next_2d() -> (f32, f32) {
+  CURRENT.with(|current| current.borrow_mut().next_2d())
+}
+fn next_1d() -> f32 {
+  CURRENT.with(|current| current.borrow_mut().next_1d())
+}
+
+// acesso ao amostrador atual pela interface de `rand`, para quem sorteia
+// com `gen`. Cada `u32` é uma dimensão da amostra
+pub struct SamplerRng;
+pub fn rng() -> SamplerRng {
+  SamplerRng
+}
+impl RngCore for SamplerRng {
+  fn next_u32(&mut self) -> u32 {
+    (next_1d() as f64 * 4294967296.0) as u32
+  }
+  fn next_u64(&mut self) -> u64 {
+    ((self.next_u32() as u64) << 32) | self.next_u32() as u64
+  }
+  fn fill_bytes(&mut self, dest: &mut [u8]) {
+    for chunk in dest.chunks_mut(4) {
+      let bytes = self.next_u32().to_le_bytes();
+      chunk.copy_from_slice(&bytes[..chunk.len()]);
+    }
+  }
+  fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+    self.fill_bytes(dest);
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const KINDS: [SamplerKind; 4] = [SamplerKind::Independent, SamplerKind::Stratified, SamplerKind::Halton, SamplerKind::Sobol];
+
+  fn sequence(sampler: &mut Box<dyn Sampler>, x: u32, y: u32, index: u32) -> Vec<f32> {
+    sampler.start(x, y, index, 16);
+    let mut values = Vec::new();
+    for _ in 0..8 {
+      values.push(sampler.next_1d());
+      let (u, v) = sampler.next_2d();
+      values.push(u);
+      values.push(v);
+    }
+    values
+  }
+
+  #[test]
+  fn same_seed_pixel_and_index_repeat_the_sequence() {
+    for &kind in KINDS.iter() {
+      let mut a = new(kind, 42);
+      let mut b = new(kind, 42);
+      // outra amostra no meio não pode mudar o resultado
+      sequence(&mut b, 7, 1, 3);
+      assert_eq!(sequence(&mut a, 3, 5, 2), sequence(&mut b, 3, 5, 2));
+      assert!(sequence(&mut a, 3, 5, 2).iter().all(|&u| (0.0..1.0).contains(&u)));
+    }
+  }
+
+  #[test]
+  fn different_seeds_give_different_sequences() {
+    for &kind in KINDS.iter() {
+      let mut a = new(kind, 1);
+      let mut b = new(kind, 2);
+      assert_ne!(sequence(&mut a, 3, 5, 2), sequence(&mut b, 3, 5, 2));
+    }
+  }
+
+  #[test]
+  fn stratified_covers_every_stratum() {
+    let count = 16;
+    let mut sampler = new(SamplerKind::Stratified, 9);
+    let mut strata = vec![false; count as usize];
+    let mut cells = vec![false; count as usize];
+    for index in 0..count {
+      sampler.start(4, 2, index, count);
+      let u = sampler.next_1d();
+      strata[(u * count as f32) as usize] = true;
+      let (x, y) = sampler.next_2d();
+      cells[(y * 4.0) as usize * 4 + (x * 4.0) as usize] = true;
+    }
+    assert!(strata.iter().all(|&covered| covered));
+    assert!(cells.iter().all(|&covered| covered));
+  }
+}
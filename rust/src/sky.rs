@@ -0,0 +1,100 @@
+use super::base::{Vector, Color};
+use super::spectral::{xyz_to_rgb, blackbody};
+use std::f32::consts::PI;
+
+// converte a luminância do modelo (em kcd/m²) para a escala do
+// renderizador: com o sol a 45° e turbidez 3 o zênite fica em torno de 0.45
+const EXPOSURE: f32 = 1.0 / 16.0;
+
+// céu diurno analítico de Preetham, Shirley e Smits (1999).
+// A luminância (Y) e a cromaticidade (x, y) de cada direção seguem a
+// fórmula de Perez, ajustada pela turbidez e pela posição do sol
+pub struct Sky {
+  up: Vector,
+  sun: Vector, // direção para o sol
+  zenith: [f32; 3], // Y, x, y no zênite
+  perez: [[f32; 5]; 3], // coeficientes A..E para Y, x e y
+  turbidity: f32,
+  intensity: f32
+}
+impl Sky {
+  pub fn new(sun: &Vector, up: &Vector, turbidity: f32, intensity: f32) -> Sky {
+    let up = up.normalized();
+    let sun = sun.normalized();
+    let t = turbidity.max(1.0);
+    // o modelo só vale com o sol acima do horizonte
+    let theta_s = up.dot(&sun).clamp(0.0, 1.0).acos();
+
+    let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+    let zenith_y = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+    let chromaticity = |m: [[f32; 4]; 3]| {
+      let thetas = [theta_s.powi(3), theta_s.powi(2), theta_s, 1.0];
+      let ts = [t * t, t, 1.0];
+      let mut sum = 0.0;
+      for (i, row) in m.iter().enumerate() {
+        for (j, value) in row.iter().enumerate() {
+          sum += ts[i] * value * thetas[j];
+        }
+      }
+      sum
+    };
+    let zenith_x = chromaticity([
+      [ 0.00166, -0.00375,  0.00209, 0.0    ],
+      [-0.02903,  0.06377, -0.03202, 0.00394],
+      [ 0.11693, -0.21196,  0.06052, 0.25886]
+    ]);
+    let zenith_chroma_y = chromaticity([
+      [ 0.00275, -0.00610,  0.00317, 0.0    ],
+      [-0.04214,  0.08970, -0.04153, 0.00516],
+      [ 0.15346, -0.26756,  0.06670, 0.26688]
+    ]);
+    let perez = [
+      [ 0.1787*t - 1.4630, -0.3554*t + 0.4275, -0.0227*t + 5.3251,  0.1206*t - 2.5771, -0.0670*t + 0.3703],
+      [-0.0193*t - 0.2592, -0.0665*t + 0.0008, -0.0004*t + 0.2125, -0.0641*t - 0.8989, -0.0033*t + 0.0452],
+      [-0.0167*t - 0.2608, -0.0950*t + 0.0092, -0.0079*t + 0.2102, -0.0441*t - 1.6537, -0.0109*t + 0.0529]
+    ];
+    Sky {
+      up, sun, perez, intensity,
+      turbidity: t,
+      zenith: [zenith_y, zenith_x, zenith_chroma_y]
+    }
+  }
+  fn perez(c: &[f32; 5], cos_theta: f32, gamma: f32) -> f32 {
+    (1.0 + c[0] * (c[1] / cos_theta).exp()) * (1.0 + c[2] * (c[3] * gamma).exp() + c[4] * gamma.cos().powi(2))
+  }
+  pub fn radiance(&self, direction: &Vector) -> Color {
+    let direction = direction.normalized();
+    // abaixo do horizonte, repete a cor do horizonte
+    let cos_theta = self.up.dot(&direction).max(0.01);
+    let gamma = direction.dot(&self.sun).clamp(-1.0, 1.0).acos();
+    let cos_theta_s = self.up.dot(&self.sun).clamp(0.0, 1.0);
+    let theta_s = cos_theta_s.acos();
+    let mut yxy = [0.0; 3];
+    for ((value, c), zenith) in yxy.iter_mut().zip(self.perez.iter()).zip(self.zenith.iter()) {
+      *value = zenith * Sky::perez(c, cos_theta, gamma) / Sky::perez(c, 1.0, theta_s);
+    }
+    let [luminance, x, y] = yxy;
+    let luminance = luminance * EXPOSURE * self.intensity;
+    if y <= 0.0 {
+      return Color::black();
+    }
+    let rgb = xyz_to_rgb([x / y * luminance, luminance, (1.0 - x - y) / y * luminance]);
+    Color::from(rgb.r.max(0.0), rgb.g.max(0.0), rgb.b.max(0.0))
+  }
+  // cor da luz do sol depois de atravessar a atmosfera: o espectro
+  // solar atenuado pelo espalhamento de Rayleigh e pelos aerossóis,
+  // avaliado em comprimentos de onda representativos de R, G e B
+  pub fn sun_color(&self) -> Color {
+    let cos_theta = self.up.dot(&self.sun).clamp(0.0, 1.0);
+    let theta = cos_theta.acos().to_degrees();
+    // massa de ar relativa (Kasten e Young)
+    let mass = 1.0 / (cos_theta + 0.15 * (93.885 - theta).powf(-1.253));
+    let beta = 0.04608 * self.turbidity - 0.04586;
+    let transmittance = |lambda: f32| {
+      let rayleigh = (-0.008735 * lambda.powf(-4.08) * mass).exp();
+      let aerosol = (-beta * lambda.powf(-1.3) * mass).exp();
+      rayleigh * aerosol
+    };
+    blackbody(5778.0) * Color::from(transmittance(0.65), transmittance(0.55), transmittance(0.45)) * self.intensity
+  }
+}
@@ -0,0 +1,91 @@
+use super::base::Color;
+
+// faixa visível, em nanômetros
+pub const MIN_WAVELENGTH: f32 = 380.0;
+pub const MAX_WAVELENGTH: f32 = 780.0;
+
+fn gaussian(x: f32, mu: f32, sigma1: f32, sigma2: f32) -> f32 {
+  let sigma = if x < mu { sigma1 } else { sigma2 };
+  let t = (x - mu) / sigma;
+  (-0.5 * t * t).exp()
+}
+// funções de correspondência de cor CIE 1931, pela aproximação
+// analítica de Wyman, Sloan e Shirley (2013)
+pub fn cie_xyz(wavelength: f32) -> [f32; 3] {
+  let l = wavelength;
+  let x = 1.056 * gaussian(l, 599.8, 37.9, 31.0)
+        + 0.362 * gaussian(l, 442.0, 16.0, 26.7)
+        - 0.065 * gaussian(l, 501.1, 20.4, 26.2);
+  let y = 0.821 * gaussian(l, 568.8, 46.9, 40.5)
+        + 0.286 * gaussian(l, 530.9, 16.3, 31.1);
+  let z = 1.217 * gaussian(l, 437.0, 11.8, 36.0)
+        + 0.681 * gaussian(l, 459.0, 26.0, 13.8);
+  [x, y, z]
+}
+// XYZ -> RGB linear (primárias sRGB, branco D65)
+pub fn xyz_to_rgb([x, y, z]: [f32; 3]) -> Color {
+  Color::from(
+     3.2406*x - 1.5372*y - 0.4986*z,
+    -0.9689*x + 1.8758*y + 0.0415*z,
+     0.0557*x - 0.2040*y + 1.0570*z
+  )
+}
+
+// cor de um corpo negro à temperatura `kelvin`, integrando a lei de
+// Planck contra as funções CIE. Normalizada para luminância 1, de modo
+// que a temperatura só muda o tom, não o brilho
+pub fn blackbody(kelvin: f32) -> Color {
+  const STEPS: u32 = 80;
+  let mut xyz = [0.0; 3];
+  for i in 0..STEPS {
+    let wavelength = Spectrum::wavelength(i, STEPS);
+    let planck = planck(wavelength, kelvin);
+    let [x, y, z] = cie_xyz(wavelength);
+    xyz[0] += x * planck;
+    xyz[1] += y * planck;
+    xyz[2] += z * planck;
+  }
+  let rgb = xyz_to_rgb(xyz);
+  let rgb = Color::from(rgb.r.max(0.0), rgb.g.max(0.0), rgb.b.max(0.0));
+  let luminance = rgb.luminance();
+  if luminance > 0.0 { rgb / luminance } else { Color::black() }
+}
+// radiância espectral relativa (constantes de escala omitidas)
+fn planck(wavelength: f32, kelvin: f32) -> f32 {
+  let l = wavelength as f64 * 1e-9;
+  let c2 = 1.4387769e-2; // h*c/k, em m·K
+  (1.0 / (l.powi(5) * ((c2 / (l * kelvin as f64)).exp() - 1.0)) * 1e-30) as f32
+}
+
+// Converte amostras monocromáticas de volta para RGB.
+// Os comprimentos de onda ficam no centro de `samples` faixas iguais do
+// espectro visível, e o peso de cada um é normalizado para que a média
+// entre eles seja branca: sem dispersão, o resultado é exatamente a
+// mesma cor que a renderização RGB daria
+pub struct Spectrum {
+  pub samples: u32, // comprimentos de onda por raio primário
+  normalization: Color
+}
+impl Spectrum {
+  pub fn new(samples: u32) -> Spectrum {
+    let mut sum = Color::black();
+    for i in 0..samples {
+      sum += Spectrum::raw_weight(Spectrum::wavelength(i, samples));
+    }
+    Spectrum {
+      samples,
+      normalization: sum / (samples as f32)
+    }
+  }
+  fn raw_weight(wavelength: f32) -> Color {
+    xyz_to_rgb(cie_xyz(wavelength))
+  }
+  // centro da i-ésima de `n` faixas do espectro visível
+  pub fn wavelength(i: u32, n: u32) -> f32 {
+    let t = (i as f32 + 0.5) / (n as f32);
+    MIN_WAVELENGTH + t * (MAX_WAVELENGTH - MIN_WAVELENGTH)
+  }
+  pub fn weight(&self, wavelength: f32) -> Color {
+    Spectrum::raw_weight(wavelength) / &self.normalization
+  }
+}
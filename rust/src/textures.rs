@@ -0,0 +1,231 @@
+use super::base::{Color, Point};
+pub use super::config::WrapMode;
+
+pub trait Texture {
+  // `uv` são as coordenadas de textura da superfície e `point`
+  // é o ponto no espaço (usado pelas texturas procedurais 3D)
+  fn color_at(&self, uv: (f32, f32), point: &Point) -> Color;
+  // valor escalar, para texturas que controlam coeficientes (kd, ks, kt)
+  fn value_at(&self, uv: (f32, f32), point: &Point) -> f32 {
+    self.color_at(uv, point).luminance()
+  }
+}
+
+fn mix(a: &Color, b: &Color, t: f32) -> Color {
+  (1.0 - t) * a + t * b
+}
+
+// ---
+// imagens
+pub struct ImageTexture {
+  width: u32,
+  height: u32,
+  pixels: Vec<Color>,
+  wrap: WrapMode,
+  scale: [f32; 2]
+}
+impl ImageTexture {
+  pub fn open(path: &str, wrap: WrapMode, scale: [f32; 2]) -> image::ImageResult<ImageTexture> {
+    let img = image::open(path)?.to_rgb();
+    let (width, height) = img.dimensions();
+    let mut pixels = Vec::with_capacity((width * height) as usize);
+    for pixel in img.pixels() {
+      let [r, g, b] = pixel.data;
+      pixels.push(Color::from(r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0));
+    }
+    Ok(ImageTexture { width, height, pixels, wrap, scale })
+  }
+  // aplica o modo de repetição a um índice que pode estar fora da imagem
+  fn wrap_index(&self, i: i64, size: u32) -> usize {
+    let n = size as i64;
+    let i = match self.wrap {
+      WrapMode::Repeat => i.rem_euclid(n),
+      WrapMode::Clamp => i.clamp(0, n - 1),
+      WrapMode::Mirror => {
+        let i = i.rem_euclid(2 * n);
+        if i >= n { 2 * n - 1 - i } else { i }
+      }
+    };
+    i as usize
+  }
+  fn texel(&self, x: i64, y: i64) -> &Color {
+    let x = self.wrap_index(x, self.width);
+    let y = self.wrap_index(y, self.height);
+    &self.pixels[y * self.width as usize + x]
+  }
+  // amostra a textura com filtragem bilinear.
+  // (0, 0) é o canto superior esquerdo da imagem
+  pub fn sample(&self, (u, v): (f32, f32)) -> Color {
+    let x = u * self.scale[0] * self.width as f32 - 0.5;
+    let y = v * self.scale[1] * self.height as f32 - 0.5;
+    let x0 = x.floor();
+    let y0 = y.floor();
+    let fx = x - x0;
+    let fy = y - y0;
+    let x0 = x0 as i64;
+    let y0 = y0 as i64;
+
+    let top    = (1.0 - fx) * self.texel(x0, y0)     + fx * self.texel(x0 + 1, y0);
+    let bottom = (1.0 - fx) * self.texel(x0, y0 + 1) + fx * self.texel(x0 + 1, y0 + 1);
+    (1.0 - fy) * top + fy * bottom
+  }
+}
+impl Texture for ImageTexture {
+  fn color_at(&self, uv: (f32, f32), _point: &Point) -> Color {
+    self.sample(uv)
+  }
+}
+
+// ---
+// ruído de Perlin ("improved noise", 2002)
+pub struct Perlin {
+  permutation: [u8; 512]
+}
+impl Perlin {
+  pub fn new(seed: u32) -> Perlin {
+    let mut p = [0u8; 256];
+    for (i, item) in p.iter_mut().enumerate() {
+      *item = i as u8;
+    }
+    // embaralha com um gerador congruencial simples,
+    // para que a mesma semente gere sempre o mesmo padrão
+    let mut state = seed.wrapping_mul(747796405).wrapping_add(2891336453);
+    for i in (1..256).rev() {
+      state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+      let j = (state >> 8) as usize % (i + 1);
+      p.swap(i, j);
+    }
+    let mut permutation = [0u8; 512];
+    for i in 0..512 {
+      permutation[i] = p[i & 255];
+    }
+    Perlin { permutation }
+  }
+  fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+  }
+  fn lerp(t: f32, a: f32, b: f32) -> f32 {
+    a + t * (b - a)
+  }
+  fn grad(hash: u8, x: f32, y: f32, z: f32) -> f32 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 { y } else if h == 12 || h == 14 { x } else { z };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+  }
+  // ruído de gradiente, em [-1, 1]
+  pub fn noise(&self, point: &Point) -> f32 {
+    let p = &self.permutation;
+    let (xf, yf, zf) = (point.x.floor(), point.y.floor(), point.z.floor());
+    let xi = (xf as i64 & 255) as usize;
+    let yi = (yf as i64 & 255) as usize;
+    let zi = (zf as i64 & 255) as usize;
+    let x = point.x - xf;
+    let y = point.y - yf;
+    let z = point.z - zf;
+    let u = Perlin::fade(x);
+    let v = Perlin::fade(y);
+    let w = Perlin::fade(z);
+
+    let a  = p[xi] as usize + yi;
+    let aa = p[a] as usize + zi;
+    let ab = p[a + 1] as usize + zi;
+    let b  = p[xi + 1] as usize + yi;
+    let ba = p[b] as usize + zi;
+    let bb = p[b + 1] as usize + zi;
+
+    Perlin::lerp(w,
+      Perlin::lerp(v,
+        Perlin::lerp(u, Perlin::grad(p[aa], x, y, z), Perlin::grad(p[ba], x - 1.0, y, z)),
+        Perlin::lerp(u, Perlin::grad(p[ab], x, y - 1.0, z), Perlin::grad(p[bb], x - 1.0, y - 1.0, z))),
+      Perlin::lerp(v,
+        Perlin::lerp(u, Perlin::grad(p[aa + 1], x, y, z - 1.0), Perlin::grad(p[ba + 1], x - 1.0, y, z - 1.0)),
+        Perlin::lerp(u, Perlin::grad(p[ab + 1], x, y - 1.0, z - 1.0), Perlin::grad(p[bb + 1], x - 1.0, y - 1.0, z - 1.0))))
+  }
+  // soma de várias oitavas de |ruído|, em [0, ~1]
+  pub fn turbulence(&self, point: &Point, octaves: u32) -> f32 {
+    let mut sum = 0.0;
+    let mut weight = 1.0;
+    let mut p = point.clone();
+    for _ in 0..octaves {
+      sum += weight * self.noise(&p).abs();
+      weight *= 0.5;
+      p *= 2.0;
+    }
+    sum
+  }
+}
+
+// ---
+// texturas procedurais
+pub struct Checker {
+  pub colors: [Color; 2],
+  pub frequency: f32
+}
+impl Texture for Checker {
+  fn color_at(&self, _uv: (f32, f32), point: &Point) -> Color {
+    // o pequeno deslocamento evita que pontos exatamente na
+    // fronteira (como um plano em y = -1) fiquem trocando de cor
+    let f = self.frequency;
+    let x = (point.x * f + 1e-4).floor() as i64;
+    let y = (point.y * f + 1e-4).floor() as i64;
+    let z = (point.z * f + 1e-4).floor() as i64;
+    self.colors[(x + y + z).rem_euclid(2) as usize].clone()
+  }
+}
+pub struct Noise {
+  pub colors: [Color; 2],
+  pub frequency: f32,
+  pub perlin: Perlin
+}
+impl Texture for Noise {
+  fn color_at(&self, _uv: (f32, f32), point: &Point) -> Color {
+    let t = 0.5 * (1.0 + self.perlin.noise(&(point * self.frequency)));
+    mix(&self.colors[0], &self.colors[1], t)
+  }
+}
+pub struct Turbulence {
+  pub colors: [Color; 2],
+  pub frequency: f32,
+  pub octaves: u32,
+  pub perlin: Perlin
+}
+impl Texture for Turbulence {
+  fn color_at(&self, _uv: (f32, f32), point: &Point) -> Color {
+    let t = self.perlin.turbulence(&(point * self.frequency), self.octaves);
+    mix(&self.colors[0], &self.colors[1], t.min(1.0))
+  }
+}
+// veios senoidais perturbados por turbulência
+pub struct Marble {
+  pub colors: [Color; 2],
+  pub frequency: f32,
+  pub octaves: u32,
+  pub strength: f32,
+  pub perlin: Perlin
+}
+impl Texture for Marble {
+  fn color_at(&self, _uv: (f32, f32), point: &Point) -> Color {
+    let p = point * self.frequency;
+    let turbulence = self.perlin.turbulence(&p, self.octaves);
+    let t = 0.5 * (1.0 + (p.x + self.strength * turbulence).sin());
+    mix(&self.colors[0], &self.colors[1], t)
+  }
+}
+// anéis concêntricos em torno do eixo y
+pub struct Wood {
+  pub colors: [Color; 2],
+  pub frequency: f32,
+  pub octaves: u32,
+  pub strength: f32,
+  pub perlin: Perlin
+}
+impl Texture for Wood {
+  fn color_at(&self, _uv: (f32, f32), point: &Point) -> Color {
+    let p = point * self.frequency;
+    let turbulence = self.perlin.turbulence(&p, self.octaves);
+    let r = (p.x * p.x + p.z * p.z).sqrt() + self.strength * turbulence;
+    let t = r - r.floor();
+    mix(&self.colors[0], &self.colors[1], t)
+  }
+}